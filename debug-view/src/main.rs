@@ -1,8 +1,7 @@
 use raylib::prelude::*;
 use std::rc::Rc;
 use std::cell::RefCell;
-use rustboy_core::mem::{FlatMem, Mem};
-use rustboy_core::ppu::PPU;
+use rustboy_core::debug::{FlatMem, Mem, PPU};
 
 fn main() {
     let (mut rl, thread) = raylib::init()
@@ -16,7 +15,7 @@ fn main() {
     for b in ram.iter().enumerate() {
         mem.write(b.0.try_into().unwrap(), *b.1);
     }
-    mem.write(0xff47, 0b00011011);
+    mem.write(0xff47, 0b11100100);
     let bus = Rc::new(RefCell::new(mem));
     let mut ppu = PPU::init(bus);
 
@@ -0,0 +1,366 @@
+use crate::mem::IoRegisters;
+
+mod noise;
+mod square;
+mod wave;
+
+use noise::Noise;
+use square::Square;
+use wave::Wave;
+
+pub const NR10_ADDR: u16 = 0xFF10;
+const NR50_ADDR: u16 = 0xFF24;
+const NR51_ADDR: u16 = 0xFF25;
+pub const NR52_ADDR: u16 = 0xFF26;
+pub const WAVE_RAM_ADDR: u16 = 0xFF30;
+pub const WAVE_RAM_END: u16 = 0xFF3F;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+const CLOCKS_PER_SECOND: u32 = 4194304;
+//bits that always read 1, NR10 to NR52
+const READ_MASKS: [u8; 0x17] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF,
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF,
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF,
+    0xFF, 0xFF, 0x00, 0x00, 0xBF,
+    0x00, 0x00, 0x70,
+];
+
+//length timer shared by all channels, it disables the channel once it runs out
+struct Length {
+    counter: u16,
+    max: u16,
+    enabled: bool,
+}
+impl Length {
+    fn new(max: u16) -> Length {
+        Length { counter: 0, max, enabled: false }
+    }
+    fn load(&mut self, val: u8) {
+        self.counter = self.max - val as u16;
+    }
+    //false once the channel should be disabled
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter != 0;
+        }
+        true
+    }
+    //the enable and trigger bits of NRx4. when the next frame sequencer step
+    //doesn't clock length, enabling it clocks once more right away
+    fn write_control(&mut self, enable: bool, trigger: bool, frame_step: u8) -> bool {
+        let extra_clock = frame_step % 2 == 1;
+        let was_enabled = self.enabled;
+        self.enabled = enable;
+        let mut alive = true;
+        if extra_clock && enable && !was_enabled && self.counter > 0 {
+            self.counter -= 1;
+            alive = self.counter != 0 || trigger;
+        }
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if enable && extra_clock {
+                self.counter -= 1;
+            }
+        }
+        alive
+    }
+}
+//volume envelope of the square and noise channels, NRx2
+struct Envelope {
+    initial: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope { initial: 0, increase: false, period: 0, volume: 0, timer: 0 }
+    }
+    fn write(&mut self, val: u8) {
+        self.initial = val >> 4;
+        self.increase = val & 0x08 != 0;
+        self.period = val & 0x07;
+    }
+    //the top 5 bits of NRx2 double as the DAC power
+    fn dac_on(&self) -> bool {
+        self.initial != 0 || self.increase
+    }
+    fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = self.period;
+    }
+    fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+pub struct Apu {
+    power: bool,
+    //NR10 to NR52 as written, for reading back
+    regs: [u8; 0x17],
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    //next frame sequencer step, 0-7
+    frame_step: u8,
+    sample_rate: u32,
+    //t cycles times the sample rate since the last sample
+    sample_clock: u32,
+    //interleaved left and right
+    samples: Vec<i16>,
+    //dc blocking filter state per side
+    capacitors: [f32; 2],
+}
+impl Default for Apu {
+    fn default() -> Self {
+        Apu {
+            power: false,
+            regs: [0; 0x17],
+            square1: Square::new(true),
+            square2: Square::new(false),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            frame_step: 0,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            sample_clock: 0,
+            samples: Vec::new(),
+            capacitors: [0.0; 2],
+        }
+    }
+}
+impl Apu {
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    //at least one sample a second, at most one a t cycle
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.sample_rate = rate.clamp(1, CLOCKS_PER_SECOND);
+        self.sample_clock = 0;
+    }
+    //stereo samples made since the last call, left then right
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+    //one step of the 512 Hz frame sequencer, driven by DIV
+    pub fn step_frame_sequencer(&mut self) {
+        if !self.power {
+            return;
+        }
+        let step = self.frame_step;
+        if step.is_multiple_of(2) {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if step == 2 || step == 6 {
+            self.square1.clock_sweep();
+        }
+        if step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+        self.frame_step = (step + 1) % 8;
+    }
+    //advance by n t cycles, taking samples along the way
+    pub fn tick(&mut self, clocks: u8) {
+        let mut clocks = clocks as u32;
+        while clocks > 0 {
+            //cycles until the next sample is due
+            let until_sample = (CLOCKS_PER_SECOND - self.sample_clock).div_ceil(self.sample_rate);
+            let run = until_sample.min(clocks);
+            if self.power {
+                self.square1.tick(run);
+                self.square2.tick(run);
+                self.wave.tick(run);
+                self.noise.tick(run);
+            }
+            self.sample_clock += run * self.sample_rate;
+            if self.sample_clock >= CLOCKS_PER_SECOND {
+                self.sample_clock -= CLOCKS_PER_SECOND;
+                self.push_sample();
+            }
+            clocks -= run;
+        }
+    }
+    fn push_sample(&mut self) {
+        //each DAC turns 0-15 into an analog level between 1 and -1
+        let dac = |on: bool, out: u8| if on { 1.0 - out as f32 / 7.5 } else { 0.0 };
+        let outputs = [
+            dac(self.square1.dac_on(), self.square1.output()),
+            dac(self.square2.dac_on(), self.square2.output()),
+            dac(self.wave.dac_on(), self.wave.output()),
+            dac(self.noise.dac_on(), self.noise.output()),
+        ];
+        let nr50 = self.regs[(NR50_ADDR - NR10_ADDR) as usize];
+        let nr51 = self.regs[(NR51_ADDR - NR10_ADDR) as usize];
+        let mut mixed = [0.0; 2];
+        for (i, out) in outputs.iter().enumerate() {
+            //NR51's high nibble is left, low nibble right
+            if nr51 & (0x10 << i) != 0 {
+                mixed[0] += out;
+            }
+            if nr51 & (0x01 << i) != 0 {
+                mixed[1] += out;
+            }
+        }
+        let volumes = [(nr50 >> 4) & 0x07, nr50 & 0x07];
+        //the capacitor loses this much charge per sample
+        let charge = 0.999958f32.powf(CLOCKS_PER_SECOND as f32 / self.sample_rate as f32);
+        for side in 0..2 {
+            let level = mixed[side] / 4.0 * (volumes[side] + 1) as f32 / 8.0;
+            let out = level - self.capacitors[side];
+            self.capacitors[side] = level - out * charge;
+            self.samples.push((out * i16::MAX as f32) as i16);
+        }
+    }
+    fn channel_status(&self) -> u8 {
+        self.square1.enabled() as u8
+            | (self.square2.enabled() as u8) << 1
+            | (self.wave.enabled() as u8) << 2
+            | (self.noise.enabled() as u8) << 3
+    }
+    fn power_off(&mut self) {
+        //everything but wave ram and the output settings is cleared
+        let wave_ram = self.wave.ram;
+        *self = Apu {
+            sample_rate: self.sample_rate,
+            sample_clock: self.sample_clock,
+            samples: std::mem::take(&mut self.samples),
+            capacitors: self.capacitors,
+            ..Apu::default()
+        };
+        self.wave.ram = wave_ram;
+    }
+}
+impl IoRegisters for Apu {
+    fn read_reg(&self, addr:u16) -> u8 {
+        match addr {
+            NR52_ADDR => (self.power as u8) << 7 | 0x70 | self.channel_status(),
+            NR10_ADDR..=NR51_ADDR => {
+                let i = (addr - NR10_ADDR) as usize;
+                self.regs[i] | READ_MASKS[i]
+            }
+            WAVE_RAM_ADDR..=WAVE_RAM_END => self.wave.ram[(addr - WAVE_RAM_ADDR) as usize],
+            _ => 0xFF,
+        }
+    }
+    fn write_reg(&mut self, addr:u16, val:u8) {
+        match addr {
+            NR52_ADDR => {
+                let power = val & 0x80 != 0;
+                if self.power && !power {
+                    self.power_off();
+                } else if !self.power && power {
+                    self.power = true;
+                    self.frame_step = 0;
+                }
+            }
+            //registers are read only while the apu is off
+            NR10_ADDR..=NR51_ADDR if self.power => {
+                self.regs[(addr - NR10_ADDR) as usize] = val;
+                let reg = ((addr - NR10_ADDR) % 5) as u8;
+                let frame_step = self.frame_step;
+                match addr {
+                    0xFF10..=0xFF14 => self.square1.write(reg, val, frame_step),
+                    0xFF15..=0xFF19 => self.square2.write(reg, val, frame_step),
+                    0xFF1A..=0xFF1E => self.wave.write(reg, val, frame_step),
+                    0xFF1F..=0xFF23 => self.noise.write(reg, val, frame_step),
+                    _ => {}
+                }
+            }
+            WAVE_RAM_ADDR..=WAVE_RAM_END => self.wave.ram[(addr - WAVE_RAM_ADDR) as usize] = val,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::apu::*;
+    use crate::mem::IoRegisters;
+    fn powered() -> Apu {
+        let mut apu = Apu::default();
+        apu.write_reg(NR52_ADDR, 0x80);
+        apu.write_reg(NR51_ADDR, 0xFF);
+        apu.write_reg(NR50_ADDR, 0x77);
+        apu
+    }
+    #[test]
+    fn length() {
+        let mut apu = powered();
+        //square 2 at full volume with 2 steps of length left
+        apu.write_reg(0xFF16, 0x3E);
+        apu.write_reg(0xFF17, 0xF0);
+        apu.write_reg(0xFF19, 0xC0);
+        assert_eq!(apu.read_reg(NR52_ADDR), 0xF2);
+        apu.step_frame_sequencer();
+        assert_eq!(apu.read_reg(NR52_ADDR), 0xF2);
+        apu.step_frame_sequencer();
+        apu.step_frame_sequencer();
+        assert_eq!(apu.read_reg(NR52_ADDR), 0xF0);
+        //turning the DAC off disables the channel too
+        apu.write_reg(0xFF19, 0x80);
+        assert_eq!(apu.read_reg(NR52_ADDR), 0xF2);
+        apu.write_reg(0xFF17, 0x00);
+        assert_eq!(apu.read_reg(NR52_ADDR), 0xF0);
+    }
+    #[test]
+    fn power() {
+        let mut apu = powered();
+        apu.write_reg(WAVE_RAM_ADDR, 0x12);
+        assert_eq!(apu.read_reg(0xFF11), 0x3F);
+        apu.write_reg(0xFF11, 0x80);
+        assert_eq!(apu.read_reg(0xFF11), 0xBF);
+        apu.write_reg(NR52_ADDR, 0x00);
+        assert_eq!(apu.read_reg(NR52_ADDR), 0x70);
+        assert_eq!(apu.read_reg(0xFF11), 0x3F);
+        //writes are ignored while off, except wave ram
+        apu.write_reg(0xFF11, 0x80);
+        assert_eq!(apu.read_reg(0xFF11), 0x3F);
+        assert_eq!(apu.read_reg(WAVE_RAM_ADDR), 0x12);
+    }
+    #[test]
+    fn samples() {
+        let mut apu = powered();
+        apu.set_sample_rate(32768);
+        //square 1, 50% duty at 1024 Hz
+        apu.write_reg(0xFF11, 0x80);
+        apu.write_reg(0xFF12, 0xF0);
+        apu.write_reg(0xFF13, 0x00);
+        apu.write_reg(0xFF14, 0x86);
+        //one frame's worth, 128 t cycles a sample
+        for _ in 0..70224 / 4 {
+            apu.tick(4);
+        }
+        let samples = apu.take_samples();
+        assert_eq!(samples.len(), 70224 / 128 * 2);
+        //a square wave swings both ways around 0
+        assert!(samples.iter().any(|&s| s > 8000) && samples.iter().any(|&s| s < -8000));
+        //left and right are the same with equal volume and panning
+        assert!(samples.chunks(2).all(|lr| lr[0] == lr[1]));
+        assert!(apu.take_samples().is_empty());
+        //rates out of range get clamped instead of breaking tick
+        apu.set_sample_rate(0);
+        assert_eq!(apu.sample_rate(), 1);
+        apu.tick(4);
+        apu.set_sample_rate(u32::MAX);
+        apu.tick(4);
+        assert_eq!(apu.take_samples().len(), 4 * 2);
+    }
+}
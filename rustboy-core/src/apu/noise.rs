@@ -0,0 +1,91 @@
+use crate::apu::{Envelope, Length};
+
+pub struct Noise {
+    length: Length,
+    envelope: Envelope,
+    shift: u8,
+    //7 bit mode, the lfsr repeats much sooner and sounds more metallic
+    short: bool,
+    divisor_code: u8,
+    timer: u32,
+    lfsr: u16,
+    enabled: bool,
+}
+impl Noise {
+    pub fn new() -> Noise {
+        Noise {
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            shift: 0,
+            short: false,
+            divisor_code: 0,
+            timer: 8,
+            lfsr: 0x7FFF,
+            enabled: false,
+        }
+    }
+    fn period(&self) -> u32 {
+        const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+        DIVISORS[self.divisor_code as usize] << self.shift
+    }
+    pub fn write(&mut self, reg: u8, val: u8, frame_step: u8) {
+        match reg {
+            //NR40 doesn't exist
+            0 => {}
+            1 => self.length.load(val & 0x3F),
+            2 => {
+                self.envelope.write(val);
+                if !self.envelope.dac_on() {
+                    self.enabled = false;
+                }
+            }
+            3 => {
+                self.shift = val >> 4;
+                self.short = val & 0x08 != 0;
+                self.divisor_code = val & 0x07;
+            }
+            4 => {
+                let trigger = val & 0x80 != 0;
+                if !self.length.write_control(val & 0x40 != 0, trigger, frame_step) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.envelope.dac_on();
+                    self.timer = self.period();
+                    self.envelope.trigger();
+                    self.lfsr = 0x7FFF;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    pub fn tick(&mut self, mut clocks: u32) {
+        while clocks >= self.timer {
+            clocks -= self.timer;
+            self.timer = self.period();
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            if self.short {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (bit << 6);
+            }
+        }
+        self.timer -= clocks;
+    }
+    pub fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    pub fn dac_on(&self) -> bool {
+        self.envelope.dac_on()
+    }
+    pub fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 1 == 0 { self.envelope.volume } else { 0 }
+    }
+}
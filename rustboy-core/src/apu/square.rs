@@ -0,0 +1,199 @@
+use crate::apu::{Envelope, Length};
+
+const DUTY_PATTERNS: [u8; 4] = [0b00000001, 0b10000001, 0b10000111, 0b01111110];
+
+//frequency sweep, only on channel 1
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    shadow: u16,
+    enabled: bool,
+    //a subtraction happened since the trigger, clearing negate then kills the channel
+    negated: bool,
+}
+impl Sweep {
+    fn new() -> Sweep {
+        Sweep { period: 0, negate: false, shift: 0, timer: 0, shadow: 0, enabled: false, negated: false }
+    }
+    //false if the write disabled the channel
+    fn write(&mut self, val: u8) -> bool {
+        self.period = (val >> 4) & 0x07;
+        self.negate = val & 0x08 != 0;
+        self.shift = val & 0x07;
+        !self.negated || self.negate
+    }
+    fn reload_timer(&mut self) {
+        //a period of 0 is treated as 8
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+    fn next_freq(&mut self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.negate {
+            self.negated = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+    fn trigger(&mut self, freq: u16) -> bool {
+        self.shadow = freq;
+        self.reload_timer();
+        self.enabled = self.period != 0 || self.shift != 0;
+        self.negated = false;
+        //the overflow check runs right away, but nothing is written back
+        self.shift == 0 || self.next_freq() <= 2047
+    }
+    fn clock(&mut self, freq: &mut u16) -> bool {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return true;
+        }
+        self.reload_timer();
+        if !self.enabled || self.period == 0 {
+            return true;
+        }
+        let next = self.next_freq();
+        if next > 2047 {
+            return false;
+        }
+        if self.shift != 0 {
+            self.shadow = next;
+            *freq = next;
+            //checked again with the new frequency, without writing it
+            return self.next_freq() <= 2047;
+        }
+        true
+    }
+}
+pub struct Square {
+    sweep: Option<Sweep>,
+    length: Length,
+    envelope: Envelope,
+    duty: u8,
+    duty_pos: u8,
+    freq: u16,
+    timer: u32,
+    enabled: bool,
+}
+impl Square {
+    pub fn new(sweep: bool) -> Square {
+        Square {
+            sweep: sweep.then(Sweep::new),
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            duty: 0,
+            duty_pos: 0,
+            freq: 0,
+            timer: 4 * 2048,
+            enabled: false,
+        }
+    }
+    fn period(&self) -> u32 {
+        (2048 - self.freq as u32) * 4
+    }
+    //reg is 0-4 for NRx0 to NRx4
+    pub fn write(&mut self, reg: u8, val: u8, frame_step: u8) {
+        match reg {
+            0 => {
+                if let Some(sweep) = &mut self.sweep {
+                    if !sweep.write(val) {
+                        self.enabled = false;
+                    }
+                }
+            }
+            1 => {
+                self.duty = val >> 6;
+                self.length.load(val & 0x3F);
+            }
+            2 => {
+                self.envelope.write(val);
+                if !self.envelope.dac_on() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.freq = (self.freq & 0x700) | val as u16,
+            4 => {
+                self.freq = (self.freq & 0xFF) | ((val as u16 & 0x07) << 8);
+                let trigger = val & 0x80 != 0;
+                if !self.length.write_control(val & 0x40 != 0, trigger, frame_step) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_on();
+        self.timer = self.period();
+        self.envelope.trigger();
+        if let Some(sweep) = &mut self.sweep {
+            if !sweep.trigger(self.freq) {
+                self.enabled = false;
+            }
+        }
+    }
+    pub fn tick(&mut self, mut clocks: u32) {
+        while clocks >= self.timer {
+            clocks -= self.timer;
+            self.timer = self.period();
+            self.duty_pos = (self.duty_pos + 1) % 8;
+        }
+        self.timer -= clocks;
+    }
+    pub fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+    pub fn clock_sweep(&mut self) {
+        if let Some(sweep) = &mut self.sweep {
+            if !sweep.clock(&mut self.freq) {
+                self.enabled = false;
+            }
+        }
+    }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    pub fn dac_on(&self) -> bool {
+        self.envelope.dac_on()
+    }
+    pub fn output(&self) -> u8 {
+        let high = DUTY_PATTERNS[self.duty as usize] & (0x80 >> self.duty_pos) != 0;
+        if self.enabled && high { self.envelope.volume } else { 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::apu::square::Square;
+    #[test]
+    fn sweep() {
+        let mut square = Square::new(true);
+        square.write(2, 0xF0, 0);
+        //period 1, shift 1, adding
+        square.write(0, 0x11, 0);
+        square.write(3, 0x00, 0);
+        square.write(4, 0x84, 0);
+        assert_eq!(square.freq, 0x400);
+        square.clock_sweep();
+        assert_eq!(square.freq, 0x600);
+        //0x600 + 0x300 is past 2047 on the follow up check
+        assert!(!square.enabled());
+        //negate then clearing it disables the channel
+        square.write(0, 0x19, 0);
+        square.write(4, 0x84, 0);
+        square.clock_sweep();
+        assert!(square.enabled());
+        square.write(0, 0x11, 0);
+        assert!(!square.enabled());
+    }
+}
@@ -0,0 +1,86 @@
+use crate::apu::Length;
+
+pub struct Wave {
+    //32 4 bit samples, high nibble first
+    pub ram: [u8; 16],
+    dac: bool,
+    length: Length,
+    volume_code: u8,
+    freq: u16,
+    timer: u32,
+    pos: u8,
+    //the sample being played, only updated when the position moves
+    sample: u8,
+    enabled: bool,
+}
+impl Wave {
+    pub fn new() -> Wave {
+        Wave {
+            ram: [0; 16],
+            dac: false,
+            length: Length::new(256),
+            volume_code: 0,
+            freq: 0,
+            timer: 2 * 2048,
+            pos: 0,
+            sample: 0,
+            enabled: false,
+        }
+    }
+    fn period(&self) -> u32 {
+        (2048 - self.freq as u32) * 2
+    }
+    pub fn write(&mut self, reg: u8, val: u8, frame_step: u8) {
+        match reg {
+            0 => {
+                self.dac = val & 0x80 != 0;
+                if !self.dac {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(val),
+            2 => self.volume_code = (val >> 5) & 0x03,
+            3 => self.freq = (self.freq & 0x700) | val as u16,
+            4 => {
+                self.freq = (self.freq & 0xFF) | ((val as u16 & 0x07) << 8);
+                let trigger = val & 0x80 != 0;
+                if !self.length.write_control(val & 0x40 != 0, trigger, frame_step) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.dac;
+                    self.timer = self.period();
+                    //the old sample keeps playing until the position moves
+                    self.pos = 0;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    pub fn tick(&mut self, mut clocks: u32) {
+        while clocks >= self.timer {
+            clocks -= self.timer;
+            self.timer = self.period();
+            self.pos = (self.pos + 1) % 32;
+            let byte = self.ram[(self.pos / 2) as usize];
+            self.sample = if self.pos.is_multiple_of(2) { byte >> 4 } else { byte & 0x0F };
+        }
+        self.timer -= clocks;
+    }
+    pub fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    pub fn dac_on(&self) -> bool {
+        self.dac
+    }
+    pub fn output(&self) -> u8 {
+        //mute, 100%, 50% and 25%
+        const SHIFTS: [u8; 4] = [4, 0, 1, 2];
+        if self.enabled { self.sample >> SHIFTS[self.volume_code as usize] } else { 0 }
+    }
+}
@@ -0,0 +1,428 @@
+mod mbc0;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+
+use std::fmt;
+use mbc0::Mbc0;
+use mbc1::Mbc1;
+use mbc2::Mbc2;
+use mbc3::{Mbc3, Rtc};
+use mbc5::Mbc5;
+
+pub use mbc3::RTC_FOOTER_LEN;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+const HEADER_END: usize = 0x150;
+const LOGO_ADDR: usize = 0x104;
+const TITLE_ADDR: usize = 0x134;
+const CGB_FLAG_ADDR: usize = 0x143;
+const SGB_FLAG_ADDR: usize = 0x146;
+const TYPE_ADDR: usize = 0x147;
+const ROM_SIZE_ADDR: usize = 0x148;
+const RAM_SIZE_ADDR: usize = 0x149;
+const HEADER_CHECKSUM_ADDR: usize = 0x14D;
+const GLOBAL_CHECKSUM_ADDR: usize = 0x14E;
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83,
+    0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
+    0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63,
+    0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CartridgeError {
+    //not even big enough to hold a header
+    TooSmall(usize),
+    UnsupportedType(u8),
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+    //the header promises more rom than the file has
+    Truncated { expected: usize, actual: usize },
+    HeaderChecksum { expected: u8, computed: u8 },
+    //save file doesn't fit the cartridge's ram, optionally followed by an rtc footer
+    SaveSize { expected: usize, actual: usize },
+}
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::TooSmall(len) => write!(f, "rom is only {} bytes, too small for a header", len),
+            CartridgeError::UnsupportedType(code) => write!(f, "unsupported cartridge type {:02X}", code),
+            CartridgeError::InvalidRomSize(code) => write!(f, "invalid rom size code {:02X}", code),
+            CartridgeError::InvalidRamSize(code) => write!(f, "invalid ram size code {:02X}", code),
+            CartridgeError::Truncated { expected, actual } => {
+                write!(f, "header declares {} bytes of rom but only {} were given", expected, actual)
+            }
+            CartridgeError::HeaderChecksum { expected, computed } => {
+                write!(f, "header checksum is {:02X} but should be {:02X}", expected, computed)
+            }
+            CartridgeError::SaveSize { expected, actual } => {
+                write!(f, "save should be {} bytes of ram but is {} bytes", expected, actual)
+            }
+        }
+    }
+}
+impl std::error::Error for CartridgeError {}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MapperKind {
+    None,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+}
+//decoded 0x147 byte
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: MapperKind,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+}
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Result<CartridgeType, CartridgeError> {
+        use MapperKind::*;
+        //(mapper, ram, battery, timer, rumble)
+        let (mapper, ram, battery, timer, rumble) = match code {
+            0x00 => (None, false, false, false, false),
+            0x01 => (Mbc1, false, false, false, false),
+            0x02 => (Mbc1, true, false, false, false),
+            0x03 => (Mbc1, true, true, false, false),
+            0x05 => (Mbc2, false, false, false, false),
+            0x06 => (Mbc2, false, true, false, false),
+            0x08 => (None, true, false, false, false),
+            0x09 => (None, true, true, false, false),
+            0x0F => (Mbc3, false, true, true, false),
+            0x10 => (Mbc3, true, true, true, false),
+            0x11 => (Mbc3, false, false, false, false),
+            0x12 => (Mbc3, true, false, false, false),
+            0x13 => (Mbc3, true, true, false, false),
+            0x19 => (Mbc5, false, false, false, false),
+            0x1A => (Mbc5, true, false, false, false),
+            0x1B => (Mbc5, true, true, false, false),
+            0x1C => (Mbc5, false, false, false, true),
+            0x1D => (Mbc5, true, false, false, true),
+            0x1E => (Mbc5, true, true, false, true),
+            _ => return Err(CartridgeError::UnsupportedType(code)),
+        };
+        Ok(CartridgeType { code, mapper, ram, battery, timer, rumble })
+    }
+}
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CgbSupport {
+    //plain dmg game
+    None,
+    //runs on both, with color on cgb
+    Enhanced,
+    Only,
+}
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub title: String,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+impl Header {
+    pub fn parse(rom: &[u8]) -> Result<Header, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::TooSmall(rom.len()));
+        }
+        //hardware only looks at bit 7, bit 6 is the cartridge saying it
+        //won't run without color
+        let flag = rom[CGB_FLAG_ADDR];
+        let cgb = if flag & 0x80 == 0 {
+            CgbSupport::None
+        } else if flag & 0x40 != 0 {
+            CgbSupport::Only
+        } else {
+            CgbSupport::Enhanced
+        };
+        //the cgb flag took over the last byte of the title
+        let title_end = if cgb == CgbSupport::None { CGB_FLAG_ADDR + 1 } else { CGB_FLAG_ADDR };
+        let title_bytes = &rom[TITLE_ADDR..title_end];
+        let title_len = title_bytes.iter().position(|&b| b == 0).unwrap_or(title_bytes.len());
+        let title = String::from_utf8_lossy(&title_bytes[..title_len]).trim_end().to_string();
+        let rom_size = match rom[ROM_SIZE_ADDR] {
+            code @ 0x00..=0x08 => (2 * ROM_BANK_SIZE) << code,
+            code => return Err(CartridgeError::InvalidRomSize(code)),
+        };
+        let ram_size = match rom[RAM_SIZE_ADDR] {
+            0x00 => 0,
+            //unofficial, only seen on some homebrew
+            0x01 => 0x800,
+            0x02 => RAM_BANK_SIZE,
+            0x03 => 4 * RAM_BANK_SIZE,
+            0x04 => 16 * RAM_BANK_SIZE,
+            0x05 => 8 * RAM_BANK_SIZE,
+            code => return Err(CartridgeError::InvalidRamSize(code)),
+        };
+        Ok(Header {
+            title,
+            cartridge_type: CartridgeType::from_code(rom[TYPE_ADDR])?,
+            rom_size,
+            ram_size,
+            cgb,
+            sgb: rom[SGB_FLAG_ADDR] == 0x03,
+            header_checksum: rom[HEADER_CHECKSUM_ADDR],
+            global_checksum: u16::from_be_bytes([rom[GLOBAL_CHECKSUM_ADDR], rom[GLOBAL_CHECKSUM_ADDR + 1]]),
+        })
+    }
+}
+fn header_checksum(rom: &[u8]) -> u8 {
+    rom[TITLE_ADDR..HEADER_CHECKSUM_ADDR].iter().fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1))
+}
+//every byte except the checksum itself, hardware never checks this
+fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter().enumerate()
+        .filter(|(i, _)| *i != GLOBAL_CHECKSUM_ADDR && *i != GLOBAL_CHECKSUM_ADDR + 1)
+        .fold(0u16, |acc, (_, &b)| acc.wrapping_add(b as u16))
+}
+
+//where the MBC3 real time clock gets its seconds from
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RtcClock {
+    //wall clock time, keeps counting between sessions like the real battery
+    Host,
+    //emulated cycles, deterministic for tests
+    Cycles,
+}
+
+//banking hardware on the cartridge, addresses are the cpu's
+pub trait Mapper {
+    //0x0000-0x7FFF
+    fn read_rom(&self, addr: u16) -> u8;
+    //writes to rom are how the mapper registers get set
+    fn write_rom(&mut self, addr: u16, val: u8);
+    //0xA000-0xBFFF
+    fn read_ram(&self, addr: u16) -> u8;
+    fn write_ram(&mut self, addr: u16, val: u8);
+    //t cycles at normal speed, for mappers with their own clock
+    fn tick(&mut self, _clocks: u32) {}
+    //whether the rumble motor is currently on
+    fn rumble(&self) -> bool {
+        false
+    }
+    //external ram as stored in a save file
+    fn ram(&self) -> &[u8];
+    fn ram_mut(&mut self) -> &mut [u8];
+    //clock state in the BGB/VBA save footer, only MBC3 has one
+    fn rtc_footer(&self) -> Option<[u8; RTC_FOOTER_LEN]> {
+        None
+    }
+    fn load_rtc_footer(&mut self, _footer: &[u8]) {}
+}
+//offset into rom of addr with the given bank mapped in
+fn rom_offset(rom_len: usize, bank: usize, addr: u16) -> usize {
+    let banks = rom_len / ROM_BANK_SIZE;
+    //bank counts are powers of two, unused high bits aren't connected
+    (bank & (banks - 1)) * ROM_BANK_SIZE + (addr as usize & (ROM_BANK_SIZE - 1))
+}
+
+pub struct Cartridge {
+    header: Header,
+    //what the header checksum should have been
+    computed_header_checksum: u8,
+    global_checksum_ok: bool,
+    mapper: Box<dyn Mapper>,
+}
+impl Cartridge {
+    pub fn from_rom(rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        Cartridge::with_rtc_clock(rom, RtcClock::Host)
+    }
+    pub fn with_rtc_clock(rom: Vec<u8>, rtc_clock: RtcClock) -> Result<Cartridge, CartridgeError> {
+        let header = Header::parse(&rom)?;
+        if rom.len() < header.rom_size {
+            return Err(CartridgeError::Truncated { expected: header.rom_size, actual: rom.len() });
+        }
+        let computed_header_checksum = header_checksum(&rom);
+        let global_checksum_ok = global_checksum(&rom) == header.global_checksum;
+        let mut rom = rom;
+        rom.truncate(header.rom_size);
+        let mapper: Box<dyn Mapper> = match header.cartridge_type.mapper {
+            MapperKind::None => Box::new(Mbc0::new(rom, header.ram_size)),
+            MapperKind::Mbc1 => Box::new(Mbc1::new(rom, header.ram_size)),
+            //ram size in the header is 0, the ram is inside the mapper
+            MapperKind::Mbc2 => Box::new(Mbc2::new(rom)),
+            MapperKind::Mbc3 => {
+                let rtc = header.cartridge_type.timer.then(|| Rtc::new(rtc_clock));
+                Box::new(Mbc3::new(rom, header.ram_size, rtc))
+            }
+            MapperKind::Mbc5 => Box::new(Mbc5::new(rom, header.ram_size, header.cartridge_type.rumble)),
+        };
+        Ok(Cartridge {
+            header,
+            computed_header_checksum,
+            global_checksum_ok,
+            mapper,
+        })
+    }
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+    //the boot rom locks up on a bad header checksum, without one nothing
+    //checks it and homebrew or patched roms still run
+    pub fn check_header_checksum(&self) -> Result<(), CartridgeError> {
+        let expected = self.header.header_checksum;
+        let computed = self.computed_header_checksum;
+        if expected != computed {
+            return Err(CartridgeError::HeaderChecksum { expected, computed });
+        }
+        Ok(())
+    }
+    pub fn global_checksum_ok(&self) -> bool {
+        self.global_checksum_ok
+    }
+    pub fn read_rom(&self, addr: u16) -> u8 {
+        self.mapper.read_rom(addr)
+    }
+    pub fn write_rom(&mut self, addr: u16, val: u8) {
+        self.mapper.write_rom(addr, val);
+    }
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        self.mapper.read_ram(addr)
+    }
+    pub fn write_ram(&mut self, addr: u16, val: u8) {
+        self.mapper.write_ram(addr, val);
+    }
+    pub fn tick(&mut self, clocks: u32) {
+        self.mapper.tick(clocks);
+    }
+    //frontends poll this to drive force feedback
+    pub fn rumble(&self) -> bool {
+        self.mapper.rumble()
+    }
+    pub fn has_battery(&self) -> bool {
+        self.header.cartridge_type.battery
+    }
+    //contents of a .sav file, ram followed by the rtc footer if there's a clock
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        if !self.has_battery() {
+            return None;
+        }
+        let mut data = self.mapper.ram().to_vec();
+        if let Some(footer) = self.mapper.rtc_footer() {
+            data.extend_from_slice(&footer);
+        }
+        Some(data)
+    }
+    pub fn load_save_data(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        //some emulators write the older 44 byte footer with a 32 bit timestamp
+        const SHORT_FOOTER_LEN: usize = 44;
+        let ram_len = self.mapper.ram().len();
+        let footer_len = data.len().saturating_sub(ram_len);
+        let footer_ok = match footer_len {
+            0 => true,
+            SHORT_FOOTER_LEN | RTC_FOOTER_LEN => self.header.cartridge_type.timer,
+            _ => false,
+        };
+        if data.len() < ram_len || !footer_ok {
+            return Err(CartridgeError::SaveSize { expected: ram_len, actual: data.len() });
+        }
+        self.mapper.ram_mut().copy_from_slice(&data[..ram_len]);
+        if footer_len > 0 {
+            self.mapper.load_rtc_footer(&data[ram_len..]);
+        }
+        Ok(())
+    }
+}
+
+//build a rom with a valid header where each bank starts with its own number
+#[cfg(test)]
+pub(crate) fn test_rom(cart_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+    let banks = 2usize << rom_size;
+    let mut rom = vec![0; banks * ROM_BANK_SIZE];
+    for bank in 0..banks {
+        rom[bank * ROM_BANK_SIZE] = bank as u8;
+    }
+    rom[LOGO_ADDR..LOGO_ADDR + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+    rom[TITLE_ADDR..TITLE_ADDR + 4].copy_from_slice(b"TEST");
+    rom[TYPE_ADDR] = cart_type;
+    rom[ROM_SIZE_ADDR] = rom_size;
+    rom[RAM_SIZE_ADDR] = ram_size;
+    rom[HEADER_CHECKSUM_ADDR] = header_checksum(&rom);
+    let checksum = global_checksum(&rom);
+    rom[GLOBAL_CHECKSUM_ADDR..GLOBAL_CHECKSUM_ADDR + 2].copy_from_slice(&checksum.to_be_bytes());
+    rom
+}
+//the same with the header asking for cgb color
+#[cfg(test)]
+pub(crate) fn test_cgb_rom(cart_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+    let mut rom = test_rom(cart_type, rom_size, ram_size);
+    rom[CGB_FLAG_ADDR] = 0x80;
+    rom[HEADER_CHECKSUM_ADDR] = header_checksum(&rom);
+    let checksum = global_checksum(&rom);
+    rom[GLOBAL_CHECKSUM_ADDR..GLOBAL_CHECKSUM_ADDR + 2].copy_from_slice(&checksum.to_be_bytes());
+    rom
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::*;
+    #[test]
+    fn header() {
+        let rom = std::fs::read("test_data/dmg-acid2.gb").unwrap();
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.title, "DMG-ACID2");
+        assert_eq!(header.cartridge_type.mapper, MapperKind::None);
+        assert_eq!(header.rom_size, 0x8000);
+        assert_eq!(header.ram_size, 0);
+        assert_eq!(header.cgb, CgbSupport::None);
+        assert!(!header.sgb);
+
+        let cart = Cartridge::from_rom(test_rom(0x03, 0x02, 0x03)).unwrap();
+        assert_eq!(cart.header().title, "TEST");
+        assert!(cart.header().cartridge_type.battery);
+        assert_eq!(cart.header().rom_size, 8 * ROM_BANK_SIZE);
+        assert_eq!(cart.header().ram_size, 4 * RAM_BANK_SIZE);
+        assert!(cart.global_checksum_ok());
+        assert_eq!(cart.check_header_checksum(), Ok(()));
+        //only bit 7 makes it a color cartridge
+        for (flag, cgb) in [(0x80, CgbSupport::Enhanced), (0x84, CgbSupport::Enhanced), (0xC0, CgbSupport::Only), (0x40, CgbSupport::None)] {
+            let mut rom = test_rom(0x00, 0x00, 0x00);
+            rom[CGB_FLAG_ADDR] = flag;
+            assert_eq!(Header::parse(&rom).unwrap().cgb, cgb);
+        }
+    }
+    #[test]
+    fn save_data() {
+        let mut cart = Cartridge::from_rom(test_rom(0x03, 0x02, 0x03)).unwrap();
+        cart.write_rom(0x0000, 0x0A);
+        cart.write_ram(0xA123, 0x45);
+        let save = cart.save_data().unwrap();
+        assert_eq!(save.len(), 4 * RAM_BANK_SIZE);
+        let mut reloaded = Cartridge::from_rom(test_rom(0x03, 0x02, 0x03)).unwrap();
+        reloaded.load_save_data(&save).unwrap();
+        reloaded.write_rom(0x0000, 0x0A);
+        assert_eq!(reloaded.read_ram(0xA123), 0x45);
+        assert_eq!(reloaded.load_save_data(&save[1..]),
+            Err(CartridgeError::SaveSize { expected: 4 * RAM_BANK_SIZE, actual: 4 * RAM_BANK_SIZE - 1 }));
+        //no battery, nothing to persist
+        assert!(Cartridge::from_rom(test_rom(0x02, 0x02, 0x03)).unwrap().save_data().is_none());
+    }
+    #[test]
+    fn malformed_header() {
+        assert_eq!(Cartridge::from_rom(vec![0; 0x100]).err(), Some(CartridgeError::TooSmall(0x100)));
+        let mut rom = test_rom(0x01, 0x01, 0x00);
+        rom[HEADER_CHECKSUM_ADDR] ^= 0xFF;
+        let cart = Cartridge::from_rom(rom).unwrap();
+        assert!(matches!(cart.check_header_checksum(), Err(CartridgeError::HeaderChecksum { .. })));
+        let mut rom = test_rom(0x01, 0x01, 0x00);
+        rom[TYPE_ADDR] = 0xFD;
+        rom[HEADER_CHECKSUM_ADDR] = header_checksum(&rom);
+        assert_eq!(Cartridge::from_rom(rom).err(), Some(CartridgeError::UnsupportedType(0xFD)));
+        let mut rom = test_rom(0x01, 0x02, 0x00);
+        rom.truncate(2 * ROM_BANK_SIZE);
+        assert_eq!(Cartridge::from_rom(rom).err(),
+            Some(CartridgeError::Truncated { expected: 8 * ROM_BANK_SIZE, actual: 2 * ROM_BANK_SIZE }));
+    }
+}
@@ -0,0 +1,41 @@
+use crate::cartridge::{Mapper, RAM_BANK_SIZE};
+
+//no banking hardware, 32 KiB of rom and optionally up to 8 KiB of ram
+pub struct Mbc0 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+impl Mbc0 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Mbc0 {
+        Mbc0 {
+            rom,
+            ram: vec![0; ram_size.min(RAM_BANK_SIZE)],
+        }
+    }
+}
+impl Mapper for Mbc0 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.rom[addr as usize]
+    }
+    //nothing listens to rom writes
+    fn write_rom(&mut self, _addr: u16, _val: u8) {}
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[(addr as usize - 0xA000) % self.ram.len()]
+    }
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.ram.is_empty() {
+            return;
+        }
+        let len = self.ram.len();
+        self.ram[(addr as usize - 0xA000) % len] = val;
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
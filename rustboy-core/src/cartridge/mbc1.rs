@@ -0,0 +1,144 @@
+use crate::cartridge::{rom_offset, Mapper, LOGO_ADDR, NINTENDO_LOGO, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enable: bool,
+    //5 bit rom bank register, 0 is treated as 1
+    bank1: u8,
+    //2 bit register, upper rom bank bits or ram bank
+    bank2: u8,
+    //advanced banking mode, bank2 also applies to 0x0000-0x3FFF and ram
+    mode: bool,
+    //MBC1M wires bank2 one bit lower and ignores bank1's top bit
+    multicart: bool,
+}
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Mbc1 {
+        let multicart = is_multicart(&rom);
+        Mbc1 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enable: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart,
+        }
+    }
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+    fn ram_offset(&self, addr: u16) -> usize {
+        let bank = if self.mode { self.bank2 as usize } else { 0 };
+        (bank * RAM_BANK_SIZE + (addr as usize & (RAM_BANK_SIZE - 1))) % self.ram.len()
+    }
+}
+impl Mapper for Mbc1 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let high = (self.bank2 as usize) << self.bank2_shift();
+        let bank = match addr {
+            0x0000..=0x3FFF => {
+                if self.mode { high } else { 0 }
+            }
+            _ => {
+                let low = if self.multicart { self.bank1 & 0x0F } else { self.bank1 };
+                high | low as usize
+            }
+        };
+        self.rom[rom_offset(self.rom.len(), bank, addr)]
+    }
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                //the zero check looks at all 5 bits, even on multicarts
+                self.bank1 = val & 0x1F;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.bank2 = val & 0x03,
+            0x6000..=0x7FFF => self.mode = val & 1 != 0,
+            _ => unreachable!(),
+        }
+    }
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enable || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[self.ram_offset(addr)]
+    }
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enable || self.ram.is_empty() {
+            return;
+        }
+        let offset = self.ram_offset(addr);
+        self.ram[offset] = val;
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+//multicarts are 1 MiB and repeat the boot logo in the second game's bank 0
+fn is_multicart(rom: &[u8]) -> bool {
+    const SECOND_GAME: usize = 0x10 * ROM_BANK_SIZE;
+    rom.len() == 64 * ROM_BANK_SIZE
+        && rom[SECOND_GAME + LOGO_ADDR..SECOND_GAME + LOGO_ADDR + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{test_rom, Cartridge, ROM_BANK_SIZE};
+    #[test]
+    fn rom_banking() {
+        //2 MiB, 128 banks
+        let mut cart = Cartridge::from_rom(test_rom(0x01, 0x06, 0x00)).unwrap();
+        assert_eq!(cart.read_rom(0x4000), 1);
+        //bank 0 maps to 1
+        cart.write_rom(0x2000, 0x00);
+        assert_eq!(cart.read_rom(0x4000), 1);
+        cart.write_rom(0x2000, 0x1F);
+        cart.write_rom(0x4000, 0x03);
+        assert_eq!(cart.read_rom(0x4000), 0x7F);
+        assert_eq!(cart.read_rom(0x0000), 0);
+        //advanced mode swaps bank2 into the lower area as well
+        cart.write_rom(0x6000, 0x01);
+        assert_eq!(cart.read_rom(0x0000), 0x60);
+        //the zero check only looks at bank1, so 0x20 reads bank 0x21
+        cart.write_rom(0x2000, 0x00);
+        cart.write_rom(0x4000, 0x01);
+        assert_eq!(cart.read_rom(0x4000), 0x21);
+    }
+    #[test]
+    fn ram_banking() {
+        let mut cart = Cartridge::from_rom(test_rom(0x03, 0x05, 0x03)).unwrap();
+        cart.write_ram(0xA000, 0x12);
+        assert_eq!(cart.read_ram(0xA000), 0xFF, "ram starts disabled");
+        cart.write_rom(0x0000, 0x0A);
+        cart.write_ram(0xA000, 0x12);
+        cart.write_rom(0x6000, 0x01);
+        cart.write_rom(0x4000, 0x02);
+        cart.write_ram(0xA000, 0x34);
+        assert_eq!(cart.read_ram(0xA000), 0x34);
+        //simple mode always uses ram bank 0
+        cart.write_rom(0x6000, 0x00);
+        assert_eq!(cart.read_ram(0xA000), 0x12);
+    }
+    #[test]
+    fn multicart() {
+        let mut rom = test_rom(0x01, 0x05, 0x00);
+        let logo = rom[0x104..0x134].to_vec();
+        rom[0x10 * ROM_BANK_SIZE + 0x104..0x10 * ROM_BANK_SIZE + 0x134].copy_from_slice(&logo);
+        let mut cart = Cartridge::from_rom(rom).unwrap();
+        cart.write_rom(0x4000, 0x01);
+        cart.write_rom(0x2000, 0x12);
+        //bank2 sits at bit 4 and bank1 loses its top bit
+        assert_eq!(cart.read_rom(0x4000), 0x12);
+        cart.write_rom(0x6000, 0x01);
+        assert_eq!(cart.read_rom(0x0000), 0x10);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::cartridge::{rom_offset, Mapper};
+
+//512 half bytes of ram built into the mapper itself
+const RAM_SIZE: usize = 0x200;
+
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    //only the low nibble of each byte is stored
+    ram: [u8; RAM_SIZE],
+    ram_enable: bool,
+    //4 bits, 0 is treated as 1
+    rom_bank: u8,
+}
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Mbc2 {
+        Mbc2 {
+            rom,
+            ram: [0; RAM_SIZE],
+            ram_enable: false,
+            rom_bank: 1,
+        }
+    }
+}
+impl Mapper for Mbc2 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        };
+        self.rom[rom_offset(self.rom.len(), bank, addr)]
+    }
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        //both registers live in 0x0000-0x3FFF, address bit 8 picks which one
+        match addr {
+            0x0000..=0x3FFF if addr & 0x100 == 0 => self.ram_enable = val & 0x0F == 0x0A,
+            0x0000..=0x3FFF => {
+                self.rom_bank = val & 0x0F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xFF;
+        }
+        //the upper nibble isn't connected and reads as 1s
+        self.ram[addr as usize % RAM_SIZE] | 0xF0
+    }
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.ram_enable {
+            self.ram[addr as usize % RAM_SIZE] = val & 0x0F;
+        }
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{test_rom, Cartridge};
+    #[test]
+    fn banking_and_ram() {
+        let mut cart = Cartridge::from_rom(test_rom(0x06, 0x03, 0x00)).unwrap();
+        //bit 8 set selects the rom bank register
+        cart.write_rom(0x2100, 0x05);
+        assert_eq!(cart.read_rom(0x4000), 5);
+        cart.write_rom(0x0100, 0x00);
+        assert_eq!(cart.read_rom(0x4000), 1);
+        //bit 8 clear is ram enable
+        cart.write_rom(0x0000, 0x0A);
+        cart.write_ram(0xA000, 0x5A);
+        assert_eq!(cart.read_ram(0xA000), 0xFA);
+        //ram echoes every 512 bytes
+        assert_eq!(cart.read_ram(0xA200), 0xFA);
+    }
+}
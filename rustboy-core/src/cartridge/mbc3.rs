@@ -0,0 +1,307 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::cartridge::{rom_offset, Mapper, RtcClock, RAM_BANK_SIZE};
+
+//t cycles per second at normal speed
+const CLOCKS_PER_SECOND: u32 = 4194304;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+//day counter is 9 bits
+const DAY_LIMIT: u64 = 512;
+//5 current and 5 latched registers as u32s, then a 64 bit unix timestamp
+pub const RTC_FOOTER_LEN: usize = 48;
+
+//the clock keeps counting while the emulator is running, reads see a latched copy
+pub struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halt: bool,
+    //day counter overflowed, sticky until cleared by a write
+    carry: bool,
+    latched: [u8; 5],
+    //last value written to the latch register, latching happens on 0 then 1
+    latch_write: u8,
+    clock: RtcClock,
+    //leftover cycles that haven't made a full second yet
+    cycles: u32,
+    //host time the registers were last brought up to date
+    last_sync: SystemTime,
+}
+impl Rtc {
+    pub fn new(clock: RtcClock) -> Rtc {
+        Rtc {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halt: false,
+            carry: false,
+            latched: [0; 5],
+            latch_write: 0xFF,
+            clock,
+            cycles: 0,
+            last_sync: SystemTime::now(),
+        }
+    }
+    fn tick(&mut self, clocks: u32) {
+        if self.clock != RtcClock::Cycles || self.halt {
+            return;
+        }
+        self.cycles += clocks;
+        if self.cycles >= CLOCKS_PER_SECOND {
+            self.advance((self.cycles / CLOCKS_PER_SECOND) as u64);
+            self.cycles %= CLOCKS_PER_SECOND;
+        }
+    }
+    //catch up with the host clock, keeping the fraction of a second for next time
+    fn sync(&mut self) {
+        if self.clock != RtcClock::Host {
+            return;
+        }
+        let now = SystemTime::now();
+        let elapsed = now.duration_since(self.last_sync).unwrap_or_default().as_secs();
+        if self.halt {
+            self.last_sync = now;
+        } else if elapsed > 0 {
+            self.advance(elapsed);
+            self.last_sync += Duration::from_secs(elapsed);
+        }
+    }
+    fn advance(&mut self, seconds: u64) {
+        let total = self.days as u64 * SECONDS_PER_DAY + self.hours as u64 * 3600
+            + self.minutes as u64 * 60 + self.seconds as u64 + seconds;
+        let days = total / SECONDS_PER_DAY;
+        if days >= DAY_LIMIT {
+            self.carry = true;
+        }
+        self.days = (days % DAY_LIMIT) as u16;
+        self.hours = (total % SECONDS_PER_DAY / 3600) as u8;
+        self.minutes = (total % 3600 / 60) as u8;
+        self.seconds = (total % 60) as u8;
+    }
+    fn registers(&self) -> [u8; 5] {
+        let day_high = (self.days >> 8) as u8 & 1 | (self.halt as u8) << 6 | (self.carry as u8) << 7;
+        [self.seconds, self.minutes, self.hours, self.days as u8, day_high]
+    }
+    fn set_registers(&mut self, regs: [u8; 5]) {
+        self.seconds = regs[0] & 0x3F;
+        self.minutes = regs[1] & 0x3F;
+        self.hours = regs[2] & 0x1F;
+        self.days = regs[3] as u16 | ((regs[4] as u16 & 1) << 8);
+        self.halt = regs[4] & (1 << 6) != 0;
+        self.carry = regs[4] & (1 << 7) != 0;
+    }
+    fn footer(&self) -> [u8; RTC_FOOTER_LEN] {
+        let mut footer = [0; RTC_FOOTER_LEN];
+        for (i, reg) in self.registers().iter().chain(self.latched.iter()).enumerate() {
+            footer[i * 4..i * 4 + 4].copy_from_slice(&(*reg as u32).to_le_bytes());
+        }
+        //on the host clock the registers are exactly as of the last sync
+        let timestamp = match self.clock {
+            RtcClock::Host => self.last_sync,
+            RtcClock::Cycles => SystemTime::now(),
+        };
+        let timestamp = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        footer[40..48].copy_from_slice(&timestamp.to_le_bytes());
+        footer
+    }
+    //accepts both the 48 byte footer and the older 44 byte one
+    fn load_footer(&mut self, footer: &[u8]) {
+        let reg = |i: usize| footer[i * 4];
+        self.set_registers([reg(0), reg(1), reg(2), reg(3), reg(4)]);
+        self.latched = [reg(5), reg(6), reg(7), reg(8), reg(9)];
+        let timestamp = match footer[40..].try_into() {
+            Ok(bytes) => u64::from_le_bytes(bytes),
+            Err(_) => u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64,
+        };
+        self.cycles = 0;
+        //the battery kept the clock running while the game was off
+        self.last_sync = UNIX_EPOCH + Duration::from_secs(timestamp);
+        self.sync();
+    }
+    fn write_latch(&mut self, val: u8) {
+        if self.latch_write == 0x00 && val == 0x01 {
+            self.sync();
+            self.latched = self.registers();
+        }
+        self.latch_write = val;
+    }
+    //select is the 0x08-0x0C value written to the ram bank register
+    fn read(&self, select: u8) -> u8 {
+        //unused bits read back as 0 on hardware
+        const MASKS: [u8; 5] = [0x3F, 0x3F, 0x1F, 0xFF, 0xC1];
+        let ind = (select - 0x08) as usize;
+        self.latched[ind] & MASKS[ind]
+    }
+    fn write(&mut self, select: u8, val: u8) {
+        self.sync();
+        let mut regs = self.registers();
+        regs[(select - 0x08) as usize] = val;
+        self.set_registers(regs);
+        if select == 0x08 {
+            //writing seconds resets the divider feeding it
+            self.cycles = 0;
+        }
+        //writes show up in the latched copy right away
+        self.latched = self.registers();
+    }
+}
+
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    //enables both ram and the rtc registers
+    ram_enable: bool,
+    rom_bank: u8,
+    //0x00-0x07 selects a ram bank, 0x08-0x0C an rtc register
+    ram_select: u8,
+    rtc: Option<Rtc>,
+}
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, rtc: Option<Rtc>) -> Mbc3 {
+        Mbc3 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enable: false,
+            rom_bank: 1,
+            ram_select: 0,
+            rtc,
+        }
+    }
+    fn ram_offset(&self, addr: u16) -> usize {
+        (self.ram_select as usize * RAM_BANK_SIZE + (addr as usize & (RAM_BANK_SIZE - 1))) % self.ram.len()
+    }
+}
+impl Mapper for Mbc3 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        };
+        self.rom[rom_offset(self.rom.len(), bank, addr)]
+    }
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                //7 bits on MBC3, MBC30 carts use the 8th
+                self.rom_bank = val;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.ram_select = val & 0x0F,
+            0x6000..=0x7FFF => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(val);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xFF;
+        }
+        match (self.ram_select, &self.rtc) {
+            (0x00..=0x07, _) if !self.ram.is_empty() => self.ram[self.ram_offset(addr)],
+            (0x08..=0x0C, Some(rtc)) => rtc.read(self.ram_select),
+            _ => 0xFF,
+        }
+    }
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enable {
+            return;
+        }
+        match (self.ram_select, &mut self.rtc) {
+            (0x00..=0x07, _) if !self.ram.is_empty() => {
+                let offset = self.ram_offset(addr);
+                self.ram[offset] = val;
+            }
+            (0x08..=0x0C, Some(rtc)) => rtc.write(self.ram_select, val),
+            _ => {}
+        }
+    }
+    fn tick(&mut self, clocks: u32) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(clocks);
+        }
+    }
+    fn rtc_footer(&self) -> Option<[u8; RTC_FOOTER_LEN]> {
+        self.rtc.as_ref().map(Rtc::footer)
+    }
+    fn load_rtc_footer(&mut self, footer: &[u8]) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load_footer(footer);
+        }
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{test_rom, Cartridge, RtcClock};
+    use super::{CLOCKS_PER_SECOND, RTC_FOOTER_LEN};
+    fn latch(cart: &mut Cartridge) {
+        cart.write_rom(0x6000, 0x00);
+        cart.write_rom(0x6000, 0x01);
+    }
+    #[test]
+    fn rtc_cycles() {
+        let mut cart = Cartridge::with_rtc_clock(test_rom(0x10, 0x06, 0x03), RtcClock::Cycles).unwrap();
+        cart.write_rom(0x0000, 0x0A);
+        //one day, one hour, one minute and one second
+        for _ in 0..(24 * 3600 + 3600 + 61) {
+            cart.tick(CLOCKS_PER_SECOND);
+        }
+        cart.write_rom(0x4000, 0x08);
+        assert_eq!(cart.read_ram(0xA000), 0, "reads come from the latch");
+        latch(&mut cart);
+        let expected = [(0x08, 1), (0x09, 1), (0x0A, 1), (0x0B, 1), (0x0C, 0)];
+        for (select, val) in expected {
+            cart.write_rom(0x4000, select);
+            assert_eq!(cart.read_ram(0xA000), val, "register {:02X}", select);
+        }
+        //halting stops the count, carry sets when the day counter wraps
+        cart.write_rom(0x4000, 0x0C);
+        cart.write_ram(0xA000, 0x41);
+        cart.tick(CLOCKS_PER_SECOND * 5);
+        cart.write_rom(0x4000, 0x08);
+        latch(&mut cart);
+        assert_eq!(cart.read_ram(0xA000), 1);
+        cart.write_rom(0x4000, 0x0B);
+        cart.write_ram(0xA000, 0xFF);
+        cart.write_rom(0x4000, 0x0A);
+        cart.write_ram(0xA000, 23);
+        cart.write_rom(0x4000, 0x09);
+        cart.write_ram(0xA000, 59);
+        cart.write_rom(0x4000, 0x08);
+        cart.write_ram(0xA000, 59);
+        cart.write_rom(0x4000, 0x0C);
+        cart.write_ram(0xA000, 0x01);
+        cart.tick(CLOCKS_PER_SECOND);
+        latch(&mut cart);
+        assert_eq!(cart.read_ram(0xA000), 0x80);
+    }
+    #[test]
+    fn rtc_footer() {
+        let mut cart = Cartridge::with_rtc_clock(test_rom(0x10, 0x06, 0x03), RtcClock::Cycles).unwrap();
+        cart.write_rom(0x0000, 0x0A);
+        cart.tick(CLOCKS_PER_SECOND * 90);
+        latch(&mut cart);
+        let save = cart.save_data().unwrap();
+        assert_eq!(save.len(), 4 * 0x2000 + RTC_FOOTER_LEN);
+        let mut reloaded = Cartridge::with_rtc_clock(test_rom(0x10, 0x06, 0x03), RtcClock::Cycles).unwrap();
+        reloaded.load_save_data(&save).unwrap();
+        reloaded.write_rom(0x0000, 0x0A);
+        reloaded.write_rom(0x4000, 0x09);
+        assert_eq!(reloaded.read_ram(0xA000), 1);
+        reloaded.write_rom(0x4000, 0x08);
+        assert_eq!(reloaded.read_ram(0xA000), 30);
+    }
+}
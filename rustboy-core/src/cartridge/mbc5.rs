@@ -0,0 +1,109 @@
+use crate::cartridge::{rom_offset, Mapper, RAM_BANK_SIZE};
+
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enable: bool,
+    //9 bits, unlike the older mappers bank 0 can be mapped
+    rom_bank: u16,
+    ram_bank: u8,
+    //rumble carts take bit 3 of the ram bank register for the motor
+    has_rumble: bool,
+    rumble: bool,
+}
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> Mbc5 {
+        Mbc5 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enable: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+        }
+    }
+    fn ram_offset(&self, addr: u16) -> usize {
+        (self.ram_bank as usize * RAM_BANK_SIZE + (addr as usize & (RAM_BANK_SIZE - 1))) % self.ram.len()
+    }
+}
+impl Mapper for Mbc5 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        };
+        self.rom[rom_offset(self.rom.len(), bank, addr)]
+    }
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            //MBC5 compares the whole byte, not just the low nibble
+            0x0000..=0x1FFF => self.ram_enable = val == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | val as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((val as u16 & 1) << 8),
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble = val & 0x08 != 0;
+                    self.ram_bank = val & 0x07;
+                } else {
+                    self.ram_bank = val & 0x0F;
+                }
+            }
+            _ => {}
+        }
+    }
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enable || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[self.ram_offset(addr)]
+    }
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enable || self.ram.is_empty() {
+            return;
+        }
+        let offset = self.ram_offset(addr);
+        self.ram[offset] = val;
+    }
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{test_rom, Cartridge, ROM_BANK_SIZE};
+    #[test]
+    fn nine_bit_rom_bank() {
+        //8 MiB, 512 banks
+        let mut rom = test_rom(0x19, 0x08, 0x00);
+        //test_rom only stores the low byte of the bank number
+        rom[0x12A * ROM_BANK_SIZE] = 0xAB;
+        let mut cart = Cartridge::from_rom(rom).unwrap();
+        cart.write_rom(0x2000, 0x2A);
+        assert_eq!(cart.read_rom(0x4000), 0x2A);
+        cart.write_rom(0x3000, 0x01);
+        assert_eq!(cart.read_rom(0x4000), 0xAB);
+        cart.write_rom(0x3000, 0x00);
+        cart.write_rom(0x2000, 0x00);
+        assert_eq!(cart.read_rom(0x4000), 0x00);
+    }
+    #[test]
+    fn rumble() {
+        let mut cart = Cartridge::from_rom(test_rom(0x1E, 0x02, 0x04)).unwrap();
+        cart.write_rom(0x0000, 0x0A);
+        cart.write_rom(0x4000, 0x0B);
+        assert!(cart.rumble());
+        cart.write_ram(0xA000, 0x77);
+        //the motor bit isn't part of the ram bank
+        cart.write_rom(0x4000, 0x03);
+        assert!(!cart.rumble());
+        assert_eq!(cart.read_ram(0xA000), 0x77);
+    }
+}
@@ -1,9 +1,11 @@
-//TODO: 
-//halt and stop behavior
-//interrupts
 use crate::tables::*;
 use crate::mem::{Mem,FlatMem};
+use crate::model::Model;
+use crate::joypad::P1_ADDR;
+use crate::timer::DIV_ADDR;
 use bitflags::bitflags;
+use std::cell::RefCell;
+use std::rc::Rc;
 //used to index into flag effect arrays
 const Z:usize = 0;
 const N:usize = 1;
@@ -18,6 +20,24 @@ bitflags! {
         const _ = !0;
     }
 }
+pub const IF_ADDR: u16 = 0xFF0F;
+pub const IE_ADDR: u16 = 0xFFFF;
+bitflags! {
+    //bit order is also the dispatch priority, vblank first
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Interrupt: u8 {
+        const VBLANK = 1 << 0;
+        const STAT = 1 << 1;
+        const TIMER = 1 << 2;
+        const SERIAL = 1 << 3;
+        const JOYPAD = 1 << 4;
+    }
+}
+impl Interrupt {
+    fn vector(self) -> u16 {
+        0x40 + 8 * self.bits().trailing_zeros() as u16
+    }
+}
 #[allow(non_snake_case)]
 struct Registers {
     A: u8,
@@ -55,26 +75,79 @@ impl Registers {
     write_16!(write_de, D, E);
     write_16!(write_hl, H, L);
 }
+impl Registers {
+    fn new(af: u16, bc: u16, de: u16, hl: u16) -> Registers {
+        let mut regs = Registers {
+            A: (af >> 8) as u8,
+            F: GbFlags::from_bits_retain(af as u8),
+            B: 0,
+            C: 0,
+            D: 0,
+            E: 0,
+            H: 0,
+            L: 0,
+        };
+        regs.write_bc(bc);
+        regs.write_de(de);
+        regs.write_hl(hl);
+        regs
+    }
+    //what the boot rom leaves behind, games tell the consoles apart by
+    //A and B. the color ones set up differently for a cgb cartridge
+    fn post_boot(model: Model, cgb: bool, header_checksum: u8) -> Registers {
+        let mut regs = match model {
+            Model::Dmg0 => Registers::new(0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::Dmg => Registers::default(),
+            Model::Mgb => Registers::new(0xFFB0, 0x0013, 0x00D8, 0x014D),
+            Model::Sgb => Registers::new(0x0100, 0x0014, 0x0000, 0xC060),
+            Model::Cgb if cgb => Registers::new(0x1180, 0x0000, 0xFF56, 0x000D),
+            Model::Agb if cgb => Registers::new(0x1100, 0x0100, 0xFF56, 0x000D),
+            Model::Cgb => Registers::new(0x1180, 0x0000, 0x0008, 0x007C),
+            Model::Agb => Registers::new(0x1100, 0x0100, 0x0008, 0x007C),
+        };
+        //the dmg and mgb boot roms finish on adding up the header, which
+        //only carries when the checksum byte isn't 0
+        if matches!(model, Model::Dmg | Model::Mgb) && header_checksum == 0 {
+            regs.F.remove(GbFlags::H | GbFlags::C);
+        }
+        regs
+    }
+}
 impl Default for Registers {
     fn default() -> Self {
-        Registers {
-            A: 0x01,
-            F: GbFlags::from_bits_retain(0xB0),
-            B: 0x00,
-            C: 0x13,
-            D: 0x00,
-            E: 0xD8,
-            H: 0x01,
-            L: 0x4D,
-        }
+        Registers::new(0x01B0, 0x0013, 0x00D8, 0x014D)
     }
 }
-#[allow(non_snake_case)]
+//register values for debuggers and test harnesses
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CpuState {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+#[allow(non_snake_case, clippy::upper_case_acronyms)]
 pub struct CPU {
     regs:Registers,
     SP: u16,
     PC: u16,
-    mem: Box<dyn Mem>,
+    mem: Rc<RefCell<dyn Mem>>,
+    //interrupt master enable
+    ime: bool,
+    //EI takes effect after the following instruction
+    ime_pending: bool,
+    halted: bool,
+    //next opcode fetch doesn't increment PC
+    halt_bug: bool,
+    stopped: bool,
+    //LD B,B ran, test roms use it as a software breakpoint
+    breakpoint: bool,
 }
 impl Default for CPU {
     fn default() -> Self {
@@ -82,20 +155,58 @@ impl Default for CPU {
             regs:Registers::default(),
             SP: 0xFFFE,
             PC: 0x0100,
-            mem: Box::new(FlatMem::default()),
+            mem: Rc::new(RefCell::new(FlatMem::default())),
+            ime: false,
+            ime_pending: false,
+            halted: false,
+            halt_bug: false,
+            stopped: false,
+            breakpoint: false,
         }
     }
 }
 impl CPU {
-    //abstract over read/write for later bus
+    pub fn init(bus: Rc<RefCell<dyn Mem>>) -> CPU {
+        CPU {
+            mem: bus,
+            ..CPU::default()
+        }
+    }
+    //start at $0100 the way the boot rom hands over
+    pub fn skip_boot(&mut self, model: Model, cgb: bool, header_checksum: u8) {
+        self.regs = Registers::post_boot(model, cgb, header_checksum);
+        self.SP = 0xFFFE;
+        self.PC = 0x0100;
+    }
+    //start from nothing at $0000, for running a boot rom
+    pub fn start_boot_rom(&mut self) {
+        self.regs = Registers::new(0, 0, 0, 0);
+        self.SP = 0;
+        self.PC = 0;
+    }
+    //true once per LD B,B
+    pub fn take_breakpoint(&mut self) -> bool {
+        std::mem::take(&mut self.breakpoint)
+    }
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            a: self.regs.A,
+            f: self.regs.F.bits(),
+            b: self.regs.B,
+            c: self.regs.C,
+            d: self.regs.D,
+            e: self.regs.E,
+            h: self.regs.H,
+            l: self.regs.L,
+            sp: self.SP,
+            pc: self.PC,
+        }
+    }
     fn read_mem(&self, addr:u16) -> u8 {
-        self.mem.read(addr)
+        self.mem.borrow_mut().cpu_read(addr)
     }
     fn write_mem(&mut self, addr:u16, data:u8) {
-        self.mem.write(addr, data);
-    }
-    fn borrow_mem(&mut self, addr:u16) -> &mut u8 {
-        self.mem.borrow_mem(addr)
+        self.mem.borrow_mut().cpu_write(addr, data);
     }
     fn fetch_byte(&mut self) -> u8 {
         let val = self.read_mem(self.PC);
@@ -124,19 +235,6 @@ impl CPU {
         self.push_stack(self.PC);
         self.PC = addr;
     }
-    fn borrow_r8(&mut self, ind:u8) -> &mut u8 {
-        match ind {
-            0 => &mut self.regs.B,
-            1 => &mut self.regs.C,
-            2 => &mut self.regs.D,
-            3 => &mut self.regs.E,
-            4 => &mut self.regs.H,
-            5 => &mut self.regs.L,
-            6 => self.borrow_mem(self.regs.read_hl()),
-            7 => &mut self.regs.A,
-            _ => panic!("Invalid index")
-        }
-    }
     fn read_r8(&self, ind:u8) -> u8 {
         match ind {
             0 => self.regs.B,
@@ -236,8 +334,80 @@ impl CPU {
         }
     }
     pub fn tick(&mut self) -> u8{
+        const IDLE_CLOCKS: u8 = 4;
+        if self.stopped {
+            //joypad lines are active low, any pressed button wakes the cpu
+            if self.read_mem(P1_ADDR) & 0x0F == 0x0F {
+                return IDLE_CLOCKS;
+            }
+            self.stopped = false;
+        }
+        if self.halted {
+            //IME doesn't matter for leaving halt, only for the dispatch
+            if self.pending_interrupts().is_empty() {
+                return IDLE_CLOCKS;
+            }
+            self.halted = false;
+        }
+        if let Some(clocks) = self.handle_interrupts() {
+            return clocks;
+        }
+        let enable_ime = self.ime_pending;
         let opcode = self.fetch_byte();
-        self.execute(opcode)
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.PC = self.PC.wrapping_sub(1);
+        }
+        let clocks = self.execute(opcode);
+        //a DI right after EI cancels it
+        if enable_ime && self.ime_pending {
+            self.ime = true;
+            self.ime_pending = false;
+        }
+        clocks
+    }
+    fn pending_interrupts(&self) -> Interrupt {
+        Interrupt::from_bits_truncate(self.read_mem(IE_ADDR) & self.read_mem(IF_ADDR))
+    }
+    //dispatch the highest priority pending interrupt if IME allows it
+    fn handle_interrupts(&mut self) -> Option<u8> {
+        const DISPATCH_CLOCKS: u8 = 20;
+        if !self.ime {
+            return None;
+        }
+        let pending = self.pending_interrupts();
+        if pending.is_empty() {
+            return None;
+        }
+        //isolate the lowest set bit, which has the highest priority
+        let interrupt = Interrupt::from_bits_truncate(pending.bits() & pending.bits().wrapping_neg());
+        self.ime = false;
+        let flags = self.read_mem(IF_ADDR);
+        self.write_mem(IF_ADDR, flags & !interrupt.bits());
+        //EI, HALT with an interrupt waiting hits the halt bug with the
+        //dispatch right behind it, which returns to the HALT itself
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.PC = self.PC.wrapping_sub(1);
+        }
+        self.call(interrupt.vector());
+        Some(DISPATCH_CLOCKS)
+    }
+    fn halt(&mut self) {
+        if !self.ime && !self.pending_interrupts().is_empty() {
+            //halt is skipped and the next byte gets read twice
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
+    }
+    fn stop(&mut self) {
+        //a speed switch prepared through KEY1 happens instead of entering stop mode
+        if !self.mem.borrow_mut().speed_switch() {
+            self.stopped = true;
+        }
+        //any write resets the divider
+        self.write_mem(DIV_ADDR, 0);
     }
     fn arithmetic_eight(&mut self, id:u8, val:u8, flag_effects:&mut [Option<bool>;4]) {
         match id {
@@ -311,7 +481,8 @@ impl CPU {
                             }
                             2 => {
                                 //STOP
-                                _ = self.fetch_byte()
+                                _ = self.fetch_byte();
+                                self.stop();
                             }
                             3 => {
                                 let shift = unsafe {
@@ -377,11 +548,11 @@ impl CPU {
                                 self.regs.A = self.read_mem(self.regs.read_de());
                             }
                             (1,2) => {
-                                self.regs.A = self.read_mem(self.regs.read_hl());
+                                self.regs.A = self.mem.borrow_mut().cpu_read_inc_dec(self.regs.read_hl());
                                 self.regs.write_hl(self.regs.read_hl().wrapping_add(1));
                             }
                             (1,3) => {
-                                self.regs.A = self.read_mem(self.regs.read_hl());
+                                self.regs.A = self.mem.borrow_mut().cpu_read_inc_dec(self.regs.read_hl());
                                 self.regs.write_hl(self.regs.read_hl().wrapping_sub(1));
                             }
                             _ => unreachable!()
@@ -390,9 +561,11 @@ impl CPU {
                     3 => {
                         if q == 0 {
                             //16 bit inc
+                            self.mem.borrow_mut().cpu_inc_dec(self.read_r16_sp(p));
                             self.write_r16_sp(p, self.read_r16_sp(p).wrapping_add(1));
                         } else if q == 1 {
                             //16 bit dec
+                            self.mem.borrow_mut().cpu_inc_dec(self.read_r16_sp(p));
                             self.write_r16_sp(p, self.read_r16_sp(p).wrapping_sub(1));
                         } else {unreachable!()}
                     }
@@ -489,8 +662,12 @@ impl CPU {
                 }
             }
             1 => {
-                //8 bit LD from register
-                if !(y == 7 && z == 7) {
+                //8 bit LD from register, LD (HL),(HL) is HALT
+                if y == 6 && z == 6 {
+                    self.halt();
+                } else if opcode == 0x40 {
+                    self.breakpoint = true;
+                } else if !(y == 7 && z == 7) {
                     self.write_r8(y, self.read_r8(z));
                 }
             }
@@ -573,9 +750,10 @@ impl CPU {
                                         self.PC = addr;
                                     }
                                     1 => {
+                                        //RETI, unlike EI there's no delay
                                         let addr = self.pop_stack();
                                         self.PC = addr;
-                                        //TODO: put an interrupt here
+                                        self.ime = true;
                                     }
                                     2 => self.PC = self.regs.read_hl(),
                                     3 => self.SP = self.regs.read_hl(),
@@ -629,66 +807,54 @@ impl CPU {
                                 match x {
                                     0 => {
                                         //rotate operation y with register z
-                                        match y {
+                                        let val = self.read_r8(z);
+                                        let result = match y {
                                             0 => {
                                                 //RLC
-                                                flag_effects[C] = Some(*self.borrow_r8(z) & (1 << 7) > 0);
-                                                *self.borrow_r8(z) = self.borrow_r8(z).rotate_left(1);
+                                                flag_effects[C] = Some(val & (1 << 7) > 0);
+                                                val.rotate_left(1)
                                             }
                                             1 => {
                                                 //RRC
-                                                flag_effects[C] = Some(*self.borrow_r8(z) & 1 > 0);
-                                                *self.borrow_r8(z) = self.borrow_r8(z).rotate_right(1);
+                                                flag_effects[C] = Some(val & 1 > 0);
+                                                val.rotate_right(1)
                                             }
                                             2 => {
                                                 //RL
                                                 let carry = self.regs.F.contains(GbFlags::C);
-                                                flag_effects[C] = Some(*self.borrow_r8(z) & 1 << 7 > 0);
-                                                *self.borrow_r8(z) <<= 1;
-                                                if carry {
-                                                    *self.borrow_r8(z) |= 1;
-                                                } else {
-                                                    *self.borrow_r8(z) &= !1;
-                                                }
+                                                flag_effects[C] = Some(val & (1 << 7) > 0);
+                                                (val << 1) | carry as u8
                                             }
                                             3 => {
                                                 //RR
                                                 let carry = self.regs.F.contains(GbFlags::C);
-                                                flag_effects[C] = Some(*self.borrow_r8(z) & 1 > 0);
-                                                *self.borrow_r8(z) >>= 1;
-                                                if carry {
-                                                    *self.borrow_r8(z) |= 1 << 7;
-                                                } else {
-                                                    *self.borrow_r8(z) &= !(1 << 7);
-                                                }
+                                                flag_effects[C] = Some(val & 1 > 0);
+                                                (val >> 1) | (carry as u8) << 7
                                             }
                                             4 => {
                                                 //SLA
-                                                flag_effects[C] = Some(*self.borrow_r8(z) & (1 << 7) > 0);
-                                                *self.borrow_r8(z) <<= 1;
+                                                flag_effects[C] = Some(val & (1 << 7) > 0);
+                                                val << 1
                                             }
                                             5 => {
                                                 //SRA
-                                                flag_effects[C] = Some(*self.borrow_r8(z) & 1 > 0);
-                                                let temp = *self.borrow_r8(z) & (1 << 7);
-                                                *self.borrow_r8(z) >>= 1;
-                                                *self.borrow_r8(z) |= temp;
+                                                flag_effects[C] = Some(val & 1 > 0);
+                                                (val >> 1) | (val & (1 << 7))
                                             }
                                             6 => {
                                                 //SWAP
-                                                let reg = self.borrow_r8(z);
-                                                let low_nibble = *reg & 0xF;
-                                                *reg >>= 4;
-                                                *reg |= low_nibble << 4;
+                                                val.rotate_left(4)
                                             }
                                             7 => {
                                                 //SRL
-                                                flag_effects[C] = Some(*self.borrow_r8(z) & 1 > 0);
-                                                *self.borrow_r8(z) >>= 1;
+                                                flag_effects[C] = Some(val & 1 > 0);
+                                                val >> 1
                                             }
                                             _ => unreachable!()
-                                        }
-                                        flag_effects[Z] = Some(*self.borrow_r8(z) == 0);
+                                        };
+                                        //(HL) goes through the bus so io registers see the write
+                                        self.write_r8(z, result);
+                                        flag_effects[Z] = Some(result == 0);
                                     }
                                     1 => {
                                         let val = self.read_r8(z);
@@ -708,10 +874,17 @@ impl CPU {
                                 }
                             }
                             2..=5 => panic!("Invalid opcode {:X}", opcode),
-                            //DI
-                            6 => todo!(),
-                            //EI
-                            7 => todo!(),
+                            6 => {
+                                //DI
+                                self.ime = false;
+                                self.ime_pending = false;
+                            }
+                            7 => {
+                                //EI
+                                if !self.ime {
+                                    self.ime_pending = true;
+                                }
+                            }
                             _ => unreachable!()
                         }
                     }
@@ -829,20 +1002,14 @@ impl CPU {
 mod tests {
     use std::fs;
     use std::vec::Vec;
-    use json;
-    use crate::cpu::{CPU, GbFlags};
+    use crate::cpu::{CPU, GbFlags, Interrupt, IE_ADDR, IF_ADDR};
     #[test]
     fn jsmoo() {
         //illegal opcodes
         let mut exclude = vec![0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
-        //halt
-        exclude.push(0x76);
         //prefix
         exclude.push(0xCB);
-        //ei and di
-        exclude.push(0xF3);
-        exclude.push(0xFB);
-        //stop
+        //stop, jsmoo doesn't model it as a two byte instruction
         exclude.push(0x10);
         let unprefixed = 0x00..=0xFF;
         let prefixed = 0x00..=0xFF;
@@ -871,6 +1038,7 @@ mod tests {
                 cpu.regs.L = initial["l"].as_u8().unwrap();
                 cpu.PC = initial["pc"].as_u16().unwrap();
                 cpu.SP = initial["sp"].as_u16().unwrap();
+                cpu.ime = initial["ime"].as_u8().unwrap() == 1;
                 for entry in initial["ram"].members() {
                     cpu.write_mem(entry[0].as_u16().unwrap(), entry[1].as_u8().unwrap());
                 }
@@ -894,6 +1062,7 @@ mod tests {
                 assert_eq!(cpu.regs.L, end["l"].as_u8().unwrap(), "failed {}", test["name"]);
                 assert_eq!(cpu.PC, end["pc"].as_u16().unwrap(), "failed {}", test["name"]);
                 assert_eq!(cpu.SP, end["sp"].as_u16().unwrap(), "failed {}", test["name"]);
+                assert_eq!(cpu.ime, end["ime"].as_u8().unwrap() == 1, "failed {}", test["name"]);
                 for entry in end["ram"].members() {
                     assert_eq!(cpu.read_mem(entry[0].as_u16().unwrap()), entry[1].as_u8().unwrap(),
                     "failed {}", test["name"]);
@@ -902,4 +1071,85 @@ mod tests {
             }
         }
     }
+    #[test]
+    fn interrupt_dispatch() {
+        let mut cpu = CPU::default();
+        //EI, NOP, NOP
+        cpu.write_mem(0x0100, 0xFB);
+        cpu.write_mem(0x0101, 0x00);
+        cpu.write_mem(0x0102, 0x00);
+        cpu.write_mem(IE_ADDR, (Interrupt::TIMER | Interrupt::JOYPAD).bits());
+        cpu.write_mem(IF_ADDR, (Interrupt::TIMER | Interrupt::JOYPAD).bits());
+        assert_eq!(cpu.tick(), 4);
+        //the instruction after EI still runs before the interrupt
+        assert_eq!(cpu.tick(), 4);
+        assert_eq!(cpu.PC, 0x0102);
+        assert_eq!(cpu.tick(), 20);
+        assert_eq!(cpu.PC, 0x50);
+        assert_eq!(cpu.pop_stack(), 0x0102);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.read_mem(IF_ADDR), Interrupt::JOYPAD.bits());
+    }
+    #[test]
+    fn halt() {
+        let mut cpu = CPU::default();
+        //HALT, INC A
+        cpu.write_mem(0x0100, 0x76);
+        cpu.write_mem(0x0101, 0x3C);
+        cpu.write_mem(IE_ADDR, Interrupt::VBLANK.bits());
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(cpu.PC, 0x0101, "halted cpu shouldn't fetch");
+        //wakes without IME and carries on without dispatching
+        cpu.write_mem(IF_ADDR, Interrupt::VBLANK.bits());
+        cpu.tick();
+        assert_eq!(cpu.PC, 0x0102);
+        assert_eq!(cpu.regs.A, 0x02);
+    }
+    #[test]
+    fn breakpoint() {
+        let mut cpu = CPU::default();
+        //LD B,B, LD C,C
+        cpu.write_mem(0x0100, 0x40);
+        cpu.write_mem(0x0101, 0x49);
+        cpu.tick();
+        assert!(cpu.take_breakpoint());
+        assert!(!cpu.take_breakpoint());
+        assert_eq!(cpu.state().b, 0x00);
+        cpu.tick();
+        assert!(!cpu.take_breakpoint());
+        assert_eq!(cpu.state().c, 0x13);
+    }
+    #[test]
+    fn halt_bug() {
+        let mut cpu = CPU::default();
+        //HALT, INC A with an interrupt already pending and IME off
+        cpu.write_mem(0x0100, 0x76);
+        cpu.write_mem(0x0101, 0x3C);
+        cpu.write_mem(IE_ADDR, Interrupt::VBLANK.bits());
+        cpu.write_mem(IF_ADDR, Interrupt::VBLANK.bits());
+        cpu.tick();
+        cpu.tick();
+        cpu.tick();
+        //INC A ran twice
+        assert_eq!(cpu.PC, 0x0102);
+        assert_eq!(cpu.regs.A, 0x03);
+    }
+    #[test]
+    fn halt_bug_ei() {
+        let mut cpu = CPU::default();
+        //EI, HALT, INC A with an interrupt already pending
+        cpu.write_mem(0x0100, 0xFB);
+        cpu.write_mem(0x0101, 0x76);
+        cpu.write_mem(0x0102, 0x3C);
+        cpu.write_mem(IE_ADDR, Interrupt::VBLANK.bits());
+        cpu.write_mem(IF_ADDR, Interrupt::VBLANK.bits());
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(cpu.tick(), 20);
+        assert_eq!(cpu.PC, 0x40);
+        assert!(!cpu.halt_bug);
+        //the return address is the HALT, which runs again after RETI
+        assert_eq!(cpu.pop_stack(), 0x0101);
+    }
 }
@@ -0,0 +1,116 @@
+use crate::mem::IoRegisters;
+
+pub const DMA_ADDR: u16 = 0xFF46;
+pub const OAM_LEN: usize = 0xA0;
+//one M-cycle between the write and the first byte
+const START_DELAY: u8 = 1;
+
+//copies 160 bytes from page XX00 into oam, one byte per M-cycle. the
+//bus does the actual reads and writes, this only keeps track of where the
+//transfer is and which memory it's tying up
+#[derive(Default)]
+pub struct OamDma {
+    //last value written, reads back as is
+    reg: u8,
+    //a requested transfer and the M-cycles until it starts, the old
+    //transfer keeps going until then
+    pending: Option<(u16, u8)>,
+    source: u16,
+    //next byte to copy, None when idle
+    index: Option<usize>,
+    //last byte copied, what the cpu reads when it hits the same bus
+    bus_value: u8,
+}
+impl OamDma {
+    pub fn active(&self) -> bool {
+        self.index.is_some()
+    }
+    //advance one M-cycle, gives the (source, oam offset) to copy if any
+    pub fn step(&mut self) -> Option<(u16, usize)> {
+        if let Some((source, delay)) = self.pending {
+            if delay == 0 {
+                self.pending = None;
+                self.source = source;
+                self.index = Some(0);
+            } else {
+                self.pending = Some((source, delay - 1));
+            }
+        }
+        let index = self.index?;
+        self.index = (index + 1 < OAM_LEN).then_some(index + 1);
+        Some((self.source + index as u16, index))
+    }
+    pub fn set_bus_value(&mut self, val: u8) {
+        self.bus_value = val;
+    }
+    //what the cpu sees on a read while the transfer runs, None if the
+    //address isn't tied up. vram has its own bus, so only the bus the
+    //source is on conflicts, hram and the io registers never do
+    pub fn conflict(&self, addr: u16) -> Option<u8> {
+        if !self.active() {
+            return None;
+        }
+        let vram = |addr: u16| (0x8000..=0x9FFF).contains(&addr);
+        match addr {
+            //oam belongs to the transfer
+            0xFE00..=0xFEFF => Some(0xFF),
+            0xFF00..=0xFFFF => None,
+            _ if vram(addr) == vram(self.source) => Some(self.bus_value),
+            _ => None,
+        }
+    }
+}
+impl IoRegisters for OamDma {
+    fn read_reg(&self, addr:u16) -> u8 {
+        debug_assert_eq!(addr, DMA_ADDR);
+        self.reg
+    }
+    fn write_reg(&mut self, addr:u16, val:u8) {
+        debug_assert_eq!(addr, DMA_ADDR);
+        self.reg = val;
+        //pages above DF read from the wram echo
+        let page = if val >= 0xE0 { val - 0x20 } else { val };
+        self.pending = Some(((page as u16) << 8, START_DELAY));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dma::*;
+    use crate::mem::IoRegisters;
+    #[test]
+    fn transfer() {
+        let mut dma = OamDma::default();
+        dma.write_reg(DMA_ADDR, 0xC1);
+        assert_eq!(dma.read_reg(DMA_ADDR), 0xC1);
+        assert_eq!(dma.step(), None);
+        assert_eq!(dma.conflict(0xC000), None);
+        for i in 0..OAM_LEN {
+            assert_eq!(dma.step(), Some((0xC100 + i as u16, i)));
+        }
+        assert!(!dma.active());
+        assert_eq!(dma.step(), None);
+        //echo pages
+        dma.write_reg(DMA_ADDR, 0xFE);
+        dma.step();
+        assert_eq!(dma.step(), Some((0xDE00, 0)));
+    }
+    #[test]
+    fn conflicts() {
+        let mut dma = OamDma::default();
+        dma.write_reg(DMA_ADDR, 0x80);
+        dma.step();
+        dma.step();
+        dma.set_bus_value(0x12);
+        assert_eq!(dma.conflict(0x9000), Some(0x12));
+        assert_eq!(dma.conflict(0xFE10), Some(0xFF));
+        assert_eq!(dma.conflict(0xC000), None);
+        assert_eq!(dma.conflict(0xFF80), None);
+        dma.write_reg(DMA_ADDR, 0x00);
+        dma.step();
+        dma.step();
+        assert_eq!(dma.conflict(0x4000), Some(0x12));
+        assert_eq!(dma.conflict(0xD000), Some(0x12));
+        assert_eq!(dma.conflict(0x8000), None);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::mem::IoRegisters;
+
+pub const HDMA1_ADDR: u16 = 0xFF51;
+pub const HDMA2_ADDR: u16 = 0xFF52;
+pub const HDMA3_ADDR: u16 = 0xFF53;
+pub const HDMA4_ADDR: u16 = 0xFF54;
+pub const HDMA5_ADDR: u16 = 0xFF55;
+
+pub const BLOCK_LEN: u16 = 0x10;
+//t cycles the cpu is stopped for each block
+pub const BLOCK_CLOCKS: u32 = 32;
+const HBLANK_MODE: u8 = 0x80;
+
+//cgb vram dma, copies 16 byte blocks into the current vram bank. a
+//general purpose transfer copies everything at once, an hblank transfer
+//one block at the start of every hblank. the bus does the copying
+#[derive(Default)]
+pub struct Hdma {
+    source: u16,
+    //offset into vram
+    dest: u16,
+    //blocks left minus 1, the way HDMA5 reads back, 0x7F once done
+    len: u8,
+    hblank: bool,
+}
+impl Hdma {
+    pub fn hblank_active(&self) -> bool {
+        self.hblank
+    }
+    //a write to HDMA5, returns how many blocks to copy right away
+    pub fn write_control(&mut self, val: u8) -> u8 {
+        //clearing bit 7 during an hblank transfer stops it
+        if self.hblank && val & HBLANK_MODE == 0 {
+            self.hblank = false;
+            return 0;
+        }
+        self.len = val & 0x7F;
+        self.hblank = val & HBLANK_MODE != 0;
+        if self.hblank {
+            0
+        } else {
+            self.len + 1
+        }
+    }
+    //source and vram address of the next block, moves past it
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let block = (self.source, 0x8000 | self.dest);
+        self.source = self.source.wrapping_add(BLOCK_LEN);
+        self.dest = (self.dest + BLOCK_LEN) & 0x1FF0;
+        self.len = self.len.wrapping_sub(1) & 0x7F;
+        if self.len == 0x7F {
+            self.hblank = false;
+        }
+        block
+    }
+}
+impl IoRegisters for Hdma {
+    fn read_reg(&self, addr:u16) -> u8 {
+        match addr {
+            //bit 7 is clear while an hblank transfer is still going
+            HDMA5_ADDR => self.len | if self.hblank { 0 } else { HBLANK_MODE },
+            //the address registers are write only
+            HDMA1_ADDR..=HDMA4_ADDR => 0xFF,
+            _ => unreachable!(),
+        }
+    }
+    fn write_reg(&mut self, addr:u16, val:u8) {
+        match addr {
+            HDMA1_ADDR => self.source = (val as u16) << 8 | (self.source & 0x00FF),
+            HDMA2_ADDR => self.source = (self.source & 0xFF00) | (val & 0xF0) as u16,
+            HDMA3_ADDR => self.dest = ((val & 0x1F) as u16) << 8 | (self.dest & 0x00FF),
+            HDMA4_ADDR => self.dest = (self.dest & 0xFF00) | (val & 0xF0) as u16,
+            HDMA5_ADDR => unreachable!("HDMA5 goes through write_control"),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hdma::*;
+    use crate::mem::IoRegisters;
+    #[test]
+    fn registers() {
+        let mut hdma = Hdma::default();
+        assert_eq!(hdma.read_reg(HDMA5_ADDR), 0x80);
+        hdma.write_reg(HDMA1_ADDR, 0xC1);
+        hdma.write_reg(HDMA2_ADDR, 0x2F);
+        hdma.write_reg(HDMA3_ADDR, 0xE8);
+        hdma.write_reg(HDMA4_ADDR, 0x4F);
+        assert_eq!(hdma.read_reg(HDMA1_ADDR), 0xFF);
+        //general purpose, 2 blocks
+        assert_eq!(hdma.write_control(0x01), 2);
+        assert_eq!(hdma.next_block(), (0xC120, 0x8840));
+        assert_eq!(hdma.next_block(), (0xC130, 0x8850));
+        assert_eq!(hdma.read_reg(HDMA5_ADDR), 0xFF);
+        //hblank, 3 blocks, stopped after 1
+        assert_eq!(hdma.write_control(0x82), 0);
+        assert!(hdma.hblank_active());
+        assert_eq!(hdma.read_reg(HDMA5_ADDR), 0x02);
+        assert_eq!(hdma.next_block(), (0xC140, 0x8860));
+        assert_eq!(hdma.read_reg(HDMA5_ADDR), 0x01);
+        assert_eq!(hdma.write_control(0x00), 0);
+        assert!(!hdma.hblank_active());
+        assert_eq!(hdma.read_reg(HDMA5_ADDR), 0x81);
+        //runs to the end on its own
+        hdma.write_control(0x80);
+        hdma.next_block();
+        assert!(!hdma.hblank_active());
+        assert_eq!(hdma.read_reg(HDMA5_ADDR), 0xFF);
+    }
+}
@@ -0,0 +1,108 @@
+use bitflags::bitflags;
+
+use crate::mem::IoRegisters;
+
+pub const P1_ADDR: u16 = 0xFF00;
+
+bitflags! {
+    //pressed buttons, low nibble is the dpad and high nibble the buttons
+    //in the order they appear on P1
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+    pub struct Buttons: u8 {
+        const RIGHT = 1 << 0;
+        const LEFT = 1 << 1;
+        const UP = 1 << 2;
+        const DOWN = 1 << 3;
+        const A = 1 << 4;
+        const B = 1 << 5;
+        const SELECT = 1 << 6;
+        const START = 1 << 7;
+    }
+}
+//select lines on P1, active low
+const SELECT_DPAD: u8 = 1 << 4;
+const SELECT_BUTTONS: u8 = 1 << 5;
+
+pub struct Joypad {
+    select: u8,
+    pressed: Buttons,
+}
+impl Default for Joypad {
+    fn default() -> Self {
+        Joypad {
+            select: SELECT_DPAD | SELECT_BUTTONS,
+            pressed: Buttons::empty(),
+        }
+    }
+}
+impl Joypad {
+    //the low nibble of P1, a line reads 0 if a pressed key in any selected
+    //group is on it
+    fn lines(&self) -> u8 {
+        let mut low = 0;
+        if self.select & SELECT_DPAD == 0 {
+            low |= self.pressed.bits() & 0x0F;
+        }
+        if self.select & SELECT_BUTTONS == 0 {
+            low |= self.pressed.bits() >> 4;
+        }
+        !low & 0x0F
+    }
+    //change the pressed buttons, true if the joypad interrupt was requested
+    pub fn set_buttons(&mut self, buttons: Buttons) -> bool {
+        let before = self.lines();
+        self.pressed = buttons;
+        falling(before, self.lines())
+    }
+    pub fn buttons(&self) -> Buttons {
+        self.pressed
+    }
+    //same as write_reg, but reports whether a line fell
+    pub fn write_select(&mut self, val: u8) -> bool {
+        let before = self.lines();
+        self.write_reg(P1_ADDR, val);
+        falling(before, self.lines())
+    }
+}
+//the interrupt fires when any input line goes from high to low
+fn falling(before: u8, after: u8) -> bool {
+    before & !after != 0
+}
+impl IoRegisters for Joypad {
+    fn read_reg(&self, _addr:u16) -> u8 {
+        //top 2 bits are unused
+        0xC0 | self.select | self.lines()
+    }
+    fn write_reg(&mut self, _addr:u16, val:u8) {
+        self.select = val & (SELECT_DPAD | SELECT_BUTTONS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::joypad::*;
+    use crate::mem::IoRegisters;
+    #[test]
+    fn matrix() {
+        let mut joypad = Joypad::default();
+        assert_eq!(joypad.read_reg(P1_ADDR), 0xFF);
+        //nothing selected, so no line falls
+        assert!(!joypad.set_buttons(Buttons::A | Buttons::LEFT));
+        assert_eq!(joypad.read_reg(P1_ADDR), 0xFF);
+        //selecting a group with a held key pulls its line low
+        assert!(joypad.write_select(0x10));
+        assert_eq!(joypad.read_reg(P1_ADDR), 0xDE);
+        joypad.write_reg(P1_ADDR, 0x20);
+        assert_eq!(joypad.read_reg(P1_ADDR), 0xED);
+        //START shares a line with DOWN, only the dpad is selected
+        assert!(!joypad.set_buttons(Buttons::LEFT | Buttons::START));
+        assert!(joypad.set_buttons(Buttons::LEFT | Buttons::START | Buttons::DOWN));
+        //both groups selected, A and RIGHT share the same line
+        joypad.write_reg(P1_ADDR, 0x00);
+        assert!(!joypad.set_buttons(Buttons::empty()));
+        assert!(joypad.set_buttons(Buttons::RIGHT));
+        assert!(!joypad.set_buttons(Buttons::RIGHT | Buttons::A));
+        assert!(joypad.set_buttons(Buttons::RIGHT | Buttons::A | Buttons::B));
+        assert_eq!(joypad.read_reg(P1_ADDR), 0xC0 | 0x0C);
+    }
+}
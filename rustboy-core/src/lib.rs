@@ -1,15 +1,425 @@
-pub mod cpu;
-pub mod mem;
-pub mod ppu;
-pub mod tables;
+use std::cell::{Ref, RefCell, RefMut};
+use std::fmt;
+use std::rc::Rc;
 
-struct GameBoy {
+pub(crate) mod apu;
+pub mod cartridge;
+pub(crate) mod cpu;
+pub(crate) mod dma;
+pub(crate) mod hdma;
+pub mod joypad;
+pub(crate) mod mem;
+pub mod model;
+pub(crate) mod oam_bug;
+pub(crate) mod palette;
+pub(crate) mod ppu;
+pub mod save;
+pub(crate) mod serial;
+pub(crate) mod tables;
+pub(crate) mod timer;
+pub mod wav;
+
+pub use cpu::CpuState;
+pub use ppu::{Renderer, SCREEN_HEIGHT, SCREEN_WIDTH};
+//a bare ppu over flat memory for the debug viewer, the emulator itself
+//goes through GameBoy
+pub mod debug {
+    pub use crate::mem::{FlatMem, Mem};
+    pub use crate::ppu::PPU;
+}
+
+use cartridge::{Cartridge, CartridgeError, RtcClock};
+use joypad::Buttons;
+use mem::{Bus, Mem, CGB_BOOT_ROM_LEN, DMG_BOOT_ROM_LEN};
+use model::Model;
+
+//t cycles in one frame, 154 lines of 456 dots
+pub const FRAME_CLOCKS: u32 = 70224;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    Cartridge(CartridgeError),
+    //boot roms are 256 bytes on dmg, 2304 on cgb
+    BootRomSize(usize),
+    //a dmg boot rom for a color model or the other way around
+    BootRomModel(Model),
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Cartridge(e) => e.fmt(f),
+            Error::BootRomSize(len) => write!(f, "boot rom is {} bytes, expected {} or {}", len, DMG_BOOT_ROM_LEN, CGB_BOOT_ROM_LEN),
+            Error::BootRomModel(model) => write!(f, "{:?} needs a {} byte boot rom", model, model.boot_rom_len()),
+        }
+    }
+}
+impl std::error::Error for Error {}
+impl From<CartridgeError> for Error {
+    fn from(e: CartridgeError) -> Self {
+        Error::Cartridge(e)
+    }
+}
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub rtc_clock: RtcClock,
+    pub sample_rate: u32,
+    pub renderer: Renderer,
+    //None picks from the cartridge header
+    pub model: Option<Model>,
+    //run this from $0000 instead of starting at $0100 with the registers
+    //already set up
+    pub boot_rom: Option<Vec<u8>>,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            rtc_clock: RtcClock::Host,
+            sample_rate: apu::DEFAULT_SAMPLE_RATE,
+            renderer: Renderer::default(),
+            model: None,
+            boot_rom: None,
+        }
+    }
+}
+//the whole console, everything hangs off the one bus the cpu and ppu share
+pub struct GameBoy {
+    bus: Rc<RefCell<Bus>>,
     cpu: cpu::CPU,
     ppu: ppu::PPU,
+    //clocks run past the end of the last frame, an instruction can cross it
+    frame_overrun: u32,
 }
 impl GameBoy {
-    pub fn step(&mut self) {
-        let clocks = self.cpu.tick();
-        self.ppu.tick(clocks);
+    pub fn from_rom(rom: Vec<u8>) -> Result<GameBoy, Error> {
+        GameBoy::with_config(rom, Config::default())
+    }
+    pub fn with_config(rom: Vec<u8>, config: Config) -> Result<GameBoy, Error> {
+        let cartridge = Cartridge::with_rtc_clock(rom, config.rtc_clock)?;
+        //a boot rom says which hardware it's for, without one the cartridge does
+        let model = match (config.model, &config.boot_rom) {
+            (Some(model), Some(boot_rom)) if boot_rom.len() != model.boot_rom_len() => {
+                return Err(match Model::for_boot_rom(boot_rom.len()) {
+                    Some(_) => Error::BootRomModel(model),
+                    None => Error::BootRomSize(boot_rom.len()),
+                });
+            }
+            (Some(model), _) => model,
+            (None, Some(boot_rom)) => Model::for_boot_rom(boot_rom.len()).ok_or(Error::BootRomSize(boot_rom.len()))?,
+            (None, None) => Model::for_header(cartridge.header()),
+        };
+        let bus = Rc::new(RefCell::new(Bus::new(cartridge, model)));
+        bus.borrow_mut().apu_mut().set_sample_rate(config.sample_rate);
+        let mut cpu = cpu::CPU::init(bus.clone());
+        match config.boot_rom {
+            Some(boot_rom) => {
+                //it would never get past the header check
+                bus.borrow().cartridge().check_header_checksum()?;
+                bus.borrow_mut().map_boot_rom(boot_rom);
+                cpu.start_boot_rom();
+            }
+            None => {
+                bus.borrow_mut().skip_boot();
+                let bus = bus.borrow();
+                cpu.skip_boot(model, bus.cgb(), bus.cartridge().header().header_checksum);
+            }
+        }
+        let mut ppu = ppu::PPU::init(bus.clone());
+        ppu.set_renderer(config.renderer);
+        ppu.set_cgb(bus.borrow().cgb());
+        Ok(GameBoy {
+            cpu,
+            ppu,
+            bus,
+            frame_overrun: 0,
+        })
+    }
+    //run one instruction, or one idle step while halted or stopped for
+    //dma, returns the dots taken. that's t cycles at normal speed, in cgb
+    //double speed the cpu gets twice as many
+    pub fn step_instruction(&mut self) -> u8 {
+        //the cpu sits out cgb vram dma an M-cycle at a time
+        let stalled = self.bus.borrow_mut().take_stall_cycle();
+        let clocks = if stalled { 4 } else { self.cpu.tick() };
+        if self.bus.borrow_mut().take_mode_change() {
+            self.ppu.set_cgb(self.bus.borrow().cgb());
+        }
+        let dots = self.bus.borrow().dots(clocks);
+        self.ppu.tick(dots);
+        self.bus.borrow_mut().tick(clocks);
+        dots
+    }
+    pub fn run_frame(&mut self) {
+        let mut clocks = self.frame_overrun;
+        while clocks < FRAME_CLOCKS {
+            clocks += self.step_instruction() as u32;
+        }
+        self.frame_overrun = clocks - FRAME_CLOCKS;
+    }
+    //160x144 pixels as 0xAARRGGBB, row by row
+    pub fn framebuffer(&self) -> &[u32] {
+        self.ppu.framebuffer()
+    }
+    //interleaved stereo samples made since the last call
+    pub fn audio_samples(&mut self) -> Vec<i16> {
+        self.bus.borrow_mut().apu_mut().take_samples()
+    }
+    //bytes sent over the link cable since the last call
+    pub fn serial_output(&mut self) -> Vec<u8> {
+        self.bus.borrow_mut().take_serial_output()
+    }
+    pub fn model(&self) -> Model {
+        self.bus.borrow().model()
+    }
+    //running in color, on a color model with a cartridge that supports it.
+    //with a boot rom that's its choice, and always while it runs
+    pub fn cgb(&self) -> bool {
+        self.bus.borrow().cgb()
+    }
+    pub fn double_speed(&self) -> bool {
+        self.bus.borrow().double_speed()
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.bus.borrow().apu().sample_rate()
+    }
+    pub fn cpu_state(&self) -> CpuState {
+        self.cpu.state()
+    }
+    //true if an LD B,B ran since the last call
+    pub fn take_breakpoint(&mut self) -> bool {
+        self.cpu.take_breakpoint()
+    }
+    //read memory the way the cpu would see it, for scripts and test harnesses
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.bus.borrow().read(addr)
+    }
+    pub fn cartridge(&self) -> Ref<'_, Cartridge> {
+        Ref::map(self.bus.borrow(), |bus| bus.cartridge())
+    }
+    pub fn cartridge_mut(&mut self) -> RefMut<'_, Cartridge> {
+        RefMut::map(self.bus.borrow_mut(), |bus| bus.cartridge_mut())
+    }
+    //replace the whole set of held buttons
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.bus.borrow_mut().set_buttons(buttons);
+    }
+    pub fn press(&mut self, buttons: Buttons) {
+        let held = self.bus.borrow().buttons();
+        self.set_buttons(held | buttons);
+    }
+    pub fn release(&mut self, buttons: Buttons) {
+        let held = self.bus.borrow().buttons();
+        self.set_buttons(held - buttons);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{test_cgb_rom, test_rom};
+    use crate::joypad::Buttons;
+    use crate::mem::Mem;
+    use crate::model::Model;
+    use crate::{Config, Error, GameBoy, FRAME_CLOCKS};
+    #[test]
+    fn run_frame() {
+        //JP $0150; LD A,$80; LDH ($26),A; JR -2
+        let mut rom = test_rom(0x00, 0x00, 0x00);
+        rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        rom[0x150..0x156].copy_from_slice(&[0x3E, 0x80, 0xE0, 0x26, 0x18, 0xFE]);
+        let config = Config { sample_rate: 32768, ..Config::default() };
+        let mut gb = GameBoy::with_config(rom, config).unwrap();
+        gb.run_frame();
+        assert_eq!(gb.framebuffer().len(), 160 * 144);
+        assert_eq!(gb.peek(0xFF26) & 0x80, 0x80);
+        let samples = gb.audio_samples();
+        assert_eq!(samples.len() as u32, FRAME_CLOCKS / 128 * 2);
+        //frames are exactly 70224 clocks long on average
+        gb.run_frame();
+        assert_eq!(gb.audio_samples().len() as u32, FRAME_CLOCKS * 2 / 128 * 2 - samples.len() as u32);
+        gb.press(Buttons::A | Buttons::UP);
+        gb.release(Buttons::UP);
+        assert_eq!(gb.bus.borrow().buttons(), Buttons::A);
+    }
+    #[test]
+    fn hblank_dma() {
+        //LCDC=$91; HDMA from $0100 to $8000, 4 blocks in hblank; JR -2
+        let program = [
+            0x3E, 0x91, 0xE0, 0x40, 0x3E, 0x01, 0xE0, 0x51, 0xAF, 0xE0, 0x52, 0xE0, 0x53, 0xE0, 0x54, 0x3E, 0x83,
+            0xE0, 0x55, 0x18, 0xFE,
+        ];
+        let mut rom = test_cgb_rom(0x00, 0x00, 0x00);
+        rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        rom[0x150..0x150 + program.len()].copy_from_slice(&program);
+        let mut gb = GameBoy::from_rom(rom.clone()).unwrap();
+        assert!(gb.cgb());
+        gb.run_frame();
+        assert_eq!(gb.peek(0xFF55), 0xFF);
+        for i in 0..0x40 {
+            assert_eq!(gb.peek(0x8000 + i), rom[0x100 + i as usize]);
+        }
+        assert_eq!(gb.peek(0x8040), 0x00);
+    }
+    #[test]
+    fn speed_switch() {
+        //LD A,$01; LDH ($4D),A; STOP; JR -2
+        let program = [0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x18, 0xFE];
+        let mut rom = test_cgb_rom(0x00, 0x00, 0x00);
+        rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        rom[0x150..0x150 + program.len()].copy_from_slice(&program);
+        let mut gb = GameBoy::from_rom(rom.clone()).unwrap();
+        for _ in 0..3 {
+            gb.step_instruction();
+        }
+        assert_eq!(gb.peek(0xFF4D), 0x7F);
+        //STOP switches and resets DIV
+        assert_eq!(gb.step_instruction(), 2);
+        assert!(gb.double_speed());
+        assert_eq!(gb.peek(0xFF4D), 0xFE);
+        //the cpu gets 2 t cycles per dot, so DIV counts twice as fast
+        let mut dots = 2u32;
+        while dots < 2000 {
+            dots += gb.step_instruction() as u32;
+        }
+        assert_eq!(gb.peek(0xFF04) as u32, dots * 2 / 256);
+        //without the cgb flag KEY1 and the switch aren't there
+        rom[0x143] = 0x00;
+        rom[0x14D] = rom[0x134..0x14D].iter().fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1));
+        let mut gb = GameBoy::from_rom(rom).unwrap();
+        for _ in 0..4 {
+            gb.step_instruction();
+        }
+        assert!(!gb.double_speed());
+    }
+    #[test]
+    fn boot_rom() {
+        //NOPs up to LD A,$01; LDH ($50),A at the very end, like the real one
+        let mut boot_rom = vec![0; 0x100];
+        boot_rom[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        let rom = test_rom(0x00, 0x00, 0x00);
+        let config = Config { boot_rom: Some(boot_rom.clone()), ..Config::default() };
+        let mut gb = GameBoy::with_config(rom.clone(), config).unwrap();
+        assert_eq!(gb.cpu_state().pc, 0x0000);
+        assert_eq!(gb.cpu_state().a, 0x00);
+        assert_eq!(gb.peek(0x0000), 0x00);
+        assert_eq!(gb.peek(0x0100), rom[0x100]);
+        //the io registers start cleared, the boot rom sets them up
+        assert_eq!(gb.peek(0xFF40), 0x00);
+        while gb.cpu_state().pc != 0x0100 {
+            gb.step_instruction();
+        }
+        assert_eq!(gb.peek(0x00FD), rom[0xFD]);
+        assert_eq!(gb.peek(0xFF50), 0xFF);
+        //without one the cartridge starts right away
+        let mut gb = GameBoy::from_rom(rom.clone()).unwrap();
+        assert_eq!(gb.cpu_state().pc, 0x0100);
+        assert_eq!(gb.cpu_state().a, 0x01);
+        assert_eq!(gb.peek(0xFF40), 0x91);
+        assert_eq!(gb.peek(0xFF47), 0xFC);
+        assert_eq!(gb.peek(0xFF04), 0xAB);
+        assert_eq!(gb.peek(0xFF26), 0xF1);
+        gb.step_instruction();
+        assert_eq!(gb.cpu_state().pc, 0x0101);
+        let gb = GameBoy::from_rom(test_cgb_rom(0x00, 0x00, 0x00)).unwrap();
+        assert_eq!(gb.cpu_state().a, 0x11);
+        //cgb boot roms are bigger, and leave a hole for the header
+        let mut boot_rom = vec![0xAA; 0x900];
+        let config = Config { boot_rom: Some(boot_rom.clone()), ..Config::default() };
+        let gb = GameBoy::with_config(rom.clone(), config).unwrap();
+        assert_eq!(gb.peek(0x00FF), 0xAA);
+        assert_eq!(gb.peek(0x0134), rom[0x134]);
+        assert_eq!(gb.peek(0x0200), 0xAA);
+        //only a boot rom cares about the header checksum
+        let mut bad_header = rom.clone();
+        bad_header[0x14D] ^= 0xFF;
+        assert!(GameBoy::from_rom(bad_header.clone()).is_ok());
+        let config = Config { boot_rom: Some(boot_rom.clone()), ..Config::default() };
+        assert!(matches!(GameBoy::with_config(bad_header, config).err(), Some(Error::Cartridge(_))));
+        boot_rom.truncate(0x200);
+        let config = Config { boot_rom: Some(boot_rom), ..Config::default() };
+        assert_eq!(GameBoy::with_config(rom, config).err(), Some(Error::BootRomSize(0x200)));
+    }
+    #[test]
+    fn oam_bug() {
+        //LD HL,$FE10; INC HL; DEC HL; JR -4
+        let mut rom = test_rom(0x00, 0x00, 0x00);
+        rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        rom[0x150..0x157].copy_from_slice(&[0x21, 0x10, 0xFE, 0x23, 0x2B, 0x18, 0xFC]);
+        let run = |model| {
+            let mut gb = GameBoy::with_config(rom.clone(), Config { model: Some(model), ..Config::default() }).unwrap();
+            for i in 0..0xA0 {
+                gb.bus.borrow_mut().write(0xFE00 + i, i as u8);
+            }
+            gb.run_frame();
+            (0..0xA0).map(|i| gb.peek(0xFE00 + i)).collect::<Vec<_>>()
+        };
+        let untouched = (0..0xA0).collect::<Vec<u8>>();
+        assert_ne!(run(Model::Dmg), untouched);
+        assert_ne!(run(Model::Mgb), untouched);
+        assert_eq!(run(Model::Cgb), untouched);
+        assert_eq!(run(Model::Agb), untouched);
+    }
+    #[test]
+    fn cgb_boot_rom() {
+        //LD A,$02; LDH ($70),A; LD A,key0; LDH ($4C),A; LD A,$01; LDH ($50),A
+        let boot_rom = |key0| {
+            let mut boot_rom = vec![0; 0x900];
+            boot_rom[..12].copy_from_slice(&[0x3E, 0x02, 0xE0, 0x70, 0x3E, key0, 0xE0, 0x4C, 0x3E, 0x01, 0xE0, 0x50]);
+            Config { boot_rom: Some(boot_rom), ..Config::default() }
+        };
+        //the boot rom runs in color even for a dmg cartridge, then drops to
+        //dmg mode like it was told
+        let mut gb = GameBoy::with_config(test_rom(0x00, 0x00, 0x00), boot_rom(0x04)).unwrap();
+        assert_eq!(gb.model(), Model::Cgb);
+        assert!(gb.cgb());
+        gb.step_instruction();
+        gb.step_instruction();
+        assert_eq!(gb.peek(0xFF70), 0xFA);
+        for _ in 0..4 {
+            gb.step_instruction();
+        }
+        assert!(!gb.cgb());
+        assert_eq!(gb.peek(0xFF70), 0xFF);
+        let mut gb = GameBoy::with_config(test_cgb_rom(0x00, 0x00, 0x00), boot_rom(0x80)).unwrap();
+        for _ in 0..6 {
+            gb.step_instruction();
+        }
+        assert!(gb.cgb());
+        assert_eq!(gb.peek(0xFF70), 0xFA);
+        //a boot rom only fits its own model
+        let config = Config { model: Some(Model::Dmg), ..boot_rom(0x04) };
+        assert_eq!(GameBoy::with_config(test_rom(0x00, 0x00, 0x00), config).err(), Some(Error::BootRomModel(Model::Dmg)));
+        let config = Config { model: Some(Model::Agb), boot_rom: Some(vec![0; 0x100]), ..Config::default() };
+        assert_eq!(GameBoy::with_config(test_rom(0x00, 0x00, 0x00), config).err(), Some(Error::BootRomModel(Model::Agb)));
+    }
+    #[test]
+    fn models() {
+        let boot = |rom: Vec<u8>, model| GameBoy::with_config(rom, Config { model: Some(model), ..Config::default() }).unwrap();
+        let dmg_rom = test_rom(0x00, 0x00, 0x00);
+        let cgb_rom = test_cgb_rom(0x00, 0x00, 0x00);
+        assert_eq!(GameBoy::from_rom(dmg_rom.clone()).unwrap().model(), Model::Dmg);
+        assert_eq!(GameBoy::from_rom(cgb_rom.clone()).unwrap().model(), Model::Cgb);
+        //a cgb cartridge on a dmg gets no color
+        let gb = boot(cgb_rom.clone(), Model::Dmg);
+        assert!(!gb.cgb());
+        assert_eq!(gb.cpu_state().a, 0x01);
+        assert_eq!(gb.peek(0xFF4F), 0xFF);
+        //a dmg cartridge on a cgb
+        let gb = boot(dmg_rom.clone(), Model::Cgb);
+        assert!(!gb.cgb());
+        assert_eq!((gb.cpu_state().a, gb.cpu_state().e, gb.cpu_state().l), (0x11, 0x08, 0x7C));
+        let gb = boot(cgb_rom, Model::Agb);
+        assert!(gb.cgb());
+        assert_eq!((gb.cpu_state().a, gb.cpu_state().f, gb.cpu_state().b), (0x11, 0x00, 0x01));
+        assert_eq!(boot(dmg_rom.clone(), Model::Mgb).cpu_state().a, 0xFF);
+        assert_eq!(boot(dmg_rom.clone(), Model::Dmg0).cpu_state().b, 0xFF);
+        //H and C are left over from adding up the header
+        assert_eq!(boot(dmg_rom.clone(), Model::Dmg).cpu_state().f, 0xB0);
+        let mut zero_checksum = dmg_rom.clone();
+        zero_checksum[0x14D] = 0x00;
+        assert_eq!(boot(zero_checksum.clone(), Model::Dmg).cpu_state().f, 0x80);
+        assert_eq!(boot(zero_checksum, Model::Mgb).cpu_state().f, 0x80);
+        //no chime on the sgb
+        let gb = boot(dmg_rom, Model::Sgb);
+        assert_eq!(gb.cpu_state().c, 0x14);
+        assert_eq!(gb.peek(0xFF26), 0xF0);
     }
 }
@@ -1,7 +1,61 @@
+use crate::apu::{Apu, NR10_ADDR, NR52_ADDR, WAVE_RAM_END};
+use crate::cartridge::{Cartridge, CgbSupport};
+use crate::cpu::{Interrupt, IE_ADDR, IF_ADDR};
+use crate::dma::{OamDma, DMA_ADDR};
+use crate::hdma::{Hdma, BLOCK_CLOCKS, BLOCK_LEN, HDMA1_ADDR, HDMA5_ADDR};
+use crate::joypad::{Buttons, Joypad, P1_ADDR};
+use crate::model::Model;
+use crate::oam_bug::{self, OamAccess};
+use crate::palette::{Palettes, BCPS_ADDR, OCPD_ADDR};
+use crate::serial::{Serial, SB_ADDR, SC_ADDR};
+use crate::timer::{Timer, DIV_ADDR, TAC_ADDR};
 pub trait Mem {
     fn read(&self, addr:u16) -> u8;
     fn write(&mut self, addr:u16, val:u8);
-    fn borrow_mem(&mut self, addr:u16) -> &mut u8;
+    //accesses by the cpu, which can be cut off from parts of memory
+    //while dma has the bus, or hit the oam bug
+    fn cpu_read(&mut self, addr:u16) -> u8 {
+        self.read(addr)
+    }
+    fn cpu_write(&mut self, addr:u16, val:u8) {
+        self.write(addr, val);
+    }
+    //LD A,(HL+) and LD A,(HL-), the read shares its M-cycle with the increment
+    fn cpu_read_inc_dec(&mut self, addr:u16) -> u8 {
+        self.cpu_read(addr)
+    }
+    //INC rr and DEC rr put the register on the address bus, nothing is accessed
+    fn cpu_inc_dec(&mut self, _addr:u16) {}
+    //the OAM row the ppu's mode 2 scan is reading, None outside of it
+    fn oam_scan_row(&mut self, _row:Option<u8>) {}
+    //writes from the hardware itself, which can set bits the cpu can't
+    fn write_hw(&mut self, addr:u16, val:u8) {
+        self.write(addr, val);
+    }
+    //vram as the ppu sees it, either cgb bank whatever VBK selects. flat
+    //memory only has the one bank
+    fn read_vram(&self, _bank:u8, addr:u16) -> u8 {
+        self.read(addr)
+    }
+    //15 bit colors from cgb palette ram, white without it
+    fn bg_color(&self, _palette:u8, _color:u8) -> u16 {
+        0x7FFF
+    }
+    fn obj_color(&self, _palette:u8, _color:u8) -> u16 {
+        0x7FFF
+    }
+    //the ppu just entered mode 0, cgb hblank dma copies a block here
+    fn hblank(&mut self) {}
+    //STOP ran, on cgb it switches speed instead if KEY1 asked for it.
+    //true if the speed changed
+    fn speed_switch(&mut self) -> bool {
+        false
+    }
+    //set the interrupt's bit in IF, the cpu services it once IE and IME allow
+    fn request_interrupt(&mut self, interrupt: Interrupt) {
+        let flags = self.read(IF_ADDR);
+        self.write(IF_ADDR, flags | interrupt.bits());
+    }
 }
 pub struct FlatMem {
     ram: [u8; 0x10000]
@@ -20,57 +74,658 @@ impl Mem for FlatMem {
     fn write(&mut self, addr:u16, data:u8) {
         self.ram[addr as usize] = data;
     }
-    fn borrow_mem(&mut self, addr:u16) -> &mut u8 {
-        &mut self.ram[addr as usize]
+}
+//memory mapped registers of a peripheral, addresses are absolute
+pub trait IoRegisters {
+    fn read_reg(&self, addr:u16) -> u8;
+    fn write_reg(&mut self, addr:u16, val:u8);
+}
+const LCDC_ADDR: u16 = 0xFF40;
+const STAT_ADDR: u16 = 0xFF41;
+const LY_ADDR: u16 = 0xFF44;
+const WX_ADDR: u16 = 0xFF4B;
+const KEY0_ADDR: u16 = 0xFF4C;
+const KEY1_ADDR: u16 = 0xFF4D;
+const VBK_ADDR: u16 = 0xFF4F;
+const BOOT_ADDR: u16 = 0xFF50;
+const SVBK_ADDR: u16 = 0xFF70;
+const VRAM_BANK_LEN: usize = 0x2000;
+const WRAM_BANK_LEN: usize = 0x1000;
+pub const DMG_BOOT_ROM_LEN: usize = 0x100;
+pub const CGB_BOOT_ROM_LEN: usize = 0x900;
+//io registers as the boot rom leaves them. the sound registers are what's
+//left of the startup chime, the apu has to be on before they take
+const POST_BOOT_IO: &[(u16, u8)] = &[
+    (NR52_ADDR, 0x80),
+    //P1, both button groups selected
+    (0xFF00, 0x00),
+    //IF, vblank
+    (0xFF0F, 0x01),
+    (0xFF10, 0x80),
+    (0xFF11, 0xBF),
+    (0xFF12, 0xF3),
+    (0xFF13, 0xFF),
+    (CHIME_TRIGGER, 0xBF),
+    (0xFF16, 0x3F),
+    (0xFF17, 0x00),
+    (0xFF18, 0xFF),
+    (0xFF19, 0xBF),
+    (0xFF1A, 0x7F),
+    (0xFF1B, 0xFF),
+    (0xFF1C, 0x9F),
+    (0xFF1D, 0xFF),
+    (0xFF1E, 0xBF),
+    (0xFF20, 0xFF),
+    (0xFF21, 0x00),
+    (0xFF22, 0x00),
+    (0xFF23, 0xBF),
+    (0xFF24, 0x77),
+    (0xFF25, 0xF3),
+    //LCDC, on with the bg showing the logo
+    (0xFF40, 0x91),
+    //BGP
+    (0xFF47, 0xFC),
+];
+//NR14, the write that starts the chime
+const CHIME_TRIGGER: u16 = 0xFF14;
+//the ppu's registers, it keeps its state in them so they're plain storage
+struct LcdRegisters {
+    regs: [u8; (WX_ADDR - LCDC_ADDR + 1) as usize],
+}
+impl IoRegisters for LcdRegisters {
+    fn read_reg(&self, addr:u16) -> u8 {
+        self.regs[(addr - LCDC_ADDR) as usize]
+    }
+    fn write_reg(&mut self, addr:u16, val:u8) {
+        self.regs[(addr - LCDC_ADDR) as usize] = val;
     }
 }
 pub struct Bus {
-    rom: [u8; 0x8000],
-    vram: [u8; 0x2000],
-    eram: [u8; 0x2000],
-    wram: [u8; 0x2000],
+    cartridge: Cartridge,
+    model: Model,
+    //covers the start of the cartridge until FF50 is written
+    boot_rom: Option<Vec<u8>>,
+    //a color model and a cartridge that asks for it, which brings in the
+    //banked memory and palettes. the cgb boot rom runs in color and picks
+    //the mode through KEY0 when it's done
+    cgb: bool,
+    //KEY0 as the cgb boot rom left it, bit 2 is dmg compatibility mode
+    key0: u8,
+    //cgb went on or off when the boot rom was unmapped, the ppu has to follow
+    mode_changed: bool,
+    //2 banks on cgb, only the first on dmg
+    vram: Box<[u8; 2 * VRAM_BANK_LEN]>,
+    vram_bank: u8,
+    //bank 0 at C000, D000 switches between 1-7 on cgb
+    wram: Box<[u8; 8 * WRAM_BANK_LEN]>,
+    wram_bank: u8,
+    palettes: Palettes,
+    //cgb double speed, the cpu, timer, serial and oam dma run twice as fast
+    double_speed: bool,
+    //KEY1 bit 0, the next STOP switches speed
+    speed_switch_armed: bool,
+    oam: [u8; 0xA0],
+    //set by the ppu during mode 2, for the oam bug
+    oam_scan_row: Option<u8>,
+    lcd: LcdRegisters,
+    joypad: Joypad,
+    serial: Serial,
+    timer: Timer,
+    apu: Apu,
+    dma: OamDma,
+    hdma: Hdma,
+    //dots the cpu still has to sit out for vram dma, it takes the same
+    //time at either speed
+    cpu_stall: u32,
+    hram: [u8; 0x7F],
+    interrupt_flag: u8,
+    interrupt_enable: u8,
+}
+impl Bus {
+    pub fn new(cartridge: Cartridge, model: Model) -> Bus {
+        let cgb = model.cgb() && cartridge.header().cgb != CgbSupport::None;
+        Bus {
+            cartridge,
+            model,
+            boot_rom: None,
+            cgb,
+            key0: 0,
+            mode_changed: false,
+            vram: Box::new([0; 2 * VRAM_BANK_LEN]),
+            vram_bank: 0,
+            wram: Box::new([0; 8 * WRAM_BANK_LEN]),
+            wram_bank: 1,
+            palettes: Palettes::default(),
+            double_speed: false,
+            speed_switch_armed: false,
+            oam: [0; 0xA0],
+            oam_scan_row: None,
+            lcd: LcdRegisters { regs: [0; (WX_ADDR - LCDC_ADDR + 1) as usize] },
+            joypad: Joypad::default(),
+            serial: Serial::default(),
+            timer: Timer::default(),
+            apu: Apu::default(),
+            dma: OamDma::default(),
+            hdma: Hdma::default(),
+            cpu_stall: 0,
+            hram: [0; 0x7F],
+            interrupt_flag: 0,
+            interrupt_enable: 0,
+        }
+    }
+    //put a boot rom over the cartridge, the cpu has to start at $0000 for it
+    pub fn map_boot_rom(&mut self, boot_rom: Vec<u8>) {
+        self.boot_rom = Some(boot_rom);
+        self.cgb = self.model.cgb();
+    }
+    //the cgb boot rom writes the cartridge's color flag to KEY0 before
+    //leaving, or 0x04 for a dmg cartridge
+    fn unmap_boot_rom(&mut self) {
+        if self.boot_rom.take().is_none() || !self.model.cgb() {
+            return;
+        }
+        let cgb = self.key0 & 0x04 == 0;
+        if !cgb {
+            self.vram_bank = 0;
+            self.wram_bank = 1;
+        }
+        self.mode_changed = cgb != self.cgb;
+        self.cgb = cgb;
+    }
+    //set up the io registers the boot rom would have
+    pub fn skip_boot(&mut self) {
+        for &(addr, val) in POST_BOOT_IO {
+            //the sgb boot rom doesn't play it
+            if addr == CHIME_TRIGGER && self.model == Model::Sgb {
+                continue;
+            }
+            self.write(addr, val);
+        }
+        //DIV's counter at the jump to the cartridge, the rest boot for a
+        //time that depends on the cartridge
+        match self.model {
+            Model::Dmg0 => self.timer.set_counter(0x1800),
+            Model::Dmg | Model::Mgb => self.timer.set_counter(0xABCC),
+            Model::Sgb => {}
+            //SC, internal clock
+            Model::Cgb | Model::Agb => self.write(SC_ADDR, 0x01),
+        }
+    }
+    //the boot rom byte at addr if it's still mapped there. the cgb one
+    //leaves a hole for the cartridge header
+    fn boot_rom_byte(&self, addr:u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x00FF | 0x0200..=0x08FF => self.boot_rom.as_ref()?.get(addr as usize).copied(),
+            _ => None,
+        }
+    }
+    pub fn model(&self) -> Model {
+        self.model
+    }
+    pub fn cgb(&self) -> bool {
+        self.cgb
+    }
+    pub fn take_mode_change(&mut self) -> bool {
+        std::mem::take(&mut self.mode_changed)
+    }
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+    //t cycles at normal speed, which the ppu, apu and real time clock run on
+    pub fn dots(&self, clocks: u8) -> u8 {
+        if self.double_speed {
+            clocks / 2
+        } else {
+            clocks
+        }
+    }
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.serial.take_output()
+    }
+    pub fn buttons(&self) -> Buttons {
+        self.joypad.buttons()
+    }
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        if self.joypad.set_buttons(buttons) {
+            self.request_interrupt(Interrupt::JOYPAD);
+        }
+    }
+    //true if the cpu is stopped for vram dma, uses up one M-cycle of it
+    pub fn take_stall_cycle(&mut self) -> bool {
+        if self.cpu_stall == 0 {
+            return false;
+        }
+        self.cpu_stall = self.cpu_stall.saturating_sub(self.dots(4) as u32);
+        true
+    }
+    fn copy_hdma_block(&mut self) {
+        let (source, dest) = self.hdma.next_block();
+        for i in 0..BLOCK_LEN {
+            let val = self.read(source.wrapping_add(i));
+            let index = self.vram_index(dest + i);
+            self.vram[index] = val;
+        }
+        self.cpu_stall += BLOCK_CLOCKS;
+    }
+    //advance the hardware living on the bus by n cpu t cycles
+    pub fn tick(&mut self, clocks: u8) {
+        let dots = self.dots(clocks);
+        self.cartridge.tick(dots as u32);
+        if self.timer.tick(clocks) {
+            self.request_interrupt(Interrupt::TIMER);
+        }
+        if self.serial.tick(clocks) {
+            self.request_interrupt(Interrupt::SERIAL);
+        }
+        for _ in 0..self.timer.take_apu_steps() {
+            self.apu.step_frame_sequencer();
+        }
+        self.apu.tick(dots);
+        for _ in 0..clocks / 4 {
+            if let Some((source, offset)) = self.dma.step() {
+                let val = self.read(source);
+                self.oam[offset] = val;
+                self.dma.set_bus_value(val);
+            }
+        }
+    }
+    fn vram_index(&self, addr:u16) -> usize {
+        self.vram_bank as usize * VRAM_BANK_LEN + (addr & 0x1FFF) as usize
+    }
+    //C000-DFFF and its echo at E000-FDFF
+    fn wram_index(&self, addr:u16) -> usize {
+        match addr & 0x1FFF {
+            offset @ 0x0000..=0x0FFF => offset as usize,
+            offset => self.wram_bank as usize * WRAM_BANK_LEN + (offset & 0x0FFF) as usize,
+        }
+    }
+    //the cpu put addr on the bus while the ppu was scanning OAM
+    fn oam_bug(&mut self, addr:u16, access: OamAccess) {
+        if let Some(row) = self.oam_scan_row {
+            if self.model.oam_bug() && (0xFE00..=0xFEFF).contains(&addr) {
+                oam_bug::corrupt(&mut self.oam, row, access);
+            }
+        }
+    }
+    //route io reads to whichever peripheral owns the register
+    fn read_io(&self, addr:u16) -> u8 {
+        match addr {
+            //upper 3 bits are unused and read high
+            P1_ADDR => self.joypad.read_reg(addr),
+            SB_ADDR | SC_ADDR => self.serial.read_reg(addr),
+            DIV_ADDR..=TAC_ADDR => self.timer.read_reg(addr),
+            IF_ADDR => self.interrupt_flag | 0xE0,
+            NR10_ADDR..=WAVE_RAM_END => self.apu.read_reg(addr),
+            DMA_ADDR => self.dma.read_reg(addr),
+            HDMA1_ADDR..=HDMA5_ADDR if self.cgb => self.hdma.read_reg(addr),
+            KEY1_ADDR if self.cgb => 0x7E | (self.double_speed as u8) << 7 | self.speed_switch_armed as u8,
+            VBK_ADDR if self.cgb => self.vram_bank | 0xFE,
+            SVBK_ADDR if self.cgb => self.wram_bank | 0xF8,
+            BCPS_ADDR..=OCPD_ADDR if self.cgb => self.palettes.read_reg(addr),
+            STAT_ADDR => self.lcd.read_reg(addr) | 0x80,
+            LCDC_ADDR..=WX_ADDR => self.lcd.read_reg(addr),
+            //nothing behind these, and the cgb ones on dmg
+            _ => 0xFF,
+        }
+    }
+    fn write_io(&mut self, addr:u16, val:u8) {
+        match addr {
+            P1_ADDR => {
+                let falling = self.joypad.write_select(val);
+                if falling {
+                    self.request_interrupt(Interrupt::JOYPAD);
+                }
+            }
+            SB_ADDR | SC_ADDR => self.serial.write_reg(addr, val),
+            DIV_ADDR..=TAC_ADDR => self.timer.write_reg(addr, val),
+            IF_ADDR => self.interrupt_flag = val & 0x1F,
+            NR10_ADDR..=WAVE_RAM_END => self.apu.write_reg(addr, val),
+            DMA_ADDR => self.dma.write_reg(addr, val),
+            //a general purpose transfer is done before the cpu goes on
+            HDMA5_ADDR if self.cgb => {
+                for _ in 0..self.hdma.write_control(val) {
+                    self.copy_hdma_block();
+                }
+            }
+            HDMA1_ADDR..HDMA5_ADDR if self.cgb => self.hdma.write_reg(addr, val),
+            KEY1_ADDR if self.cgb => self.speed_switch_armed = val & 1 != 0,
+            VBK_ADDR if self.cgb => self.vram_bank = val & 1,
+            //bank 0 can't be put at D000, asking for it gets bank 1
+            SVBK_ADDR if self.cgb => self.wram_bank = (val & 0x07).max(1),
+            BCPS_ADDR..=OCPD_ADDR if self.cgb => self.palettes.write_reg(addr, val),
+            //the mode and coincidence bits belong to the ppu
+            STAT_ADDR => {
+                let status = self.lcd.read_reg(addr) & 0x07;
+                self.lcd.write_reg(addr, (val & 0x78) | status);
+            }
+            LY_ADDR => {}
+            //only the boot rom gets to set the mode
+            KEY0_ADDR if self.boot_rom.is_some() && self.model.cgb() => self.key0 = val,
+            //unmapping the boot rom is for good
+            BOOT_ADDR if val & 1 != 0 => self.unmap_boot_rom(),
+            LCDC_ADDR..=WX_ADDR => self.lcd.write_reg(addr, val),
+            _ => {}
+        }
+    }
 }
 impl Mem for Bus {
     fn read(&self, addr:u16) -> u8 {
         match addr {
             0..=0x7FFF => {
-                self.rom[addr as usize]
+                self.boot_rom_byte(addr).unwrap_or_else(|| self.cartridge.read_rom(addr))
             }
             0x8000..=0x9FFF => {
-                self.vram[(addr & 0x1FFF) as usize]
+                self.vram[self.vram_index(addr)]
             }
             0xA000..=0xBFFF => {
-                self.eram[(addr & 0x1FFF) as usize]
+                self.cartridge.read_ram(addr)
             }
             //range is longer because of shadow wram
             0xC000..=0xFDFF => {
-                self.wram[(addr & 0x1FFF) as usize]
+                self.wram[self.wram_index(addr)]
             }
             0xFE00..=0xFE9F => {
-                //oam
-                todo!()
+                self.oam[(addr - 0xFE00) as usize]
             }
             0xFEA0..=0xFEFF => {
-                panic!("unusable memory area")
+                //unusable, on dmg it reads 0xFF while the ppu has oam locked
+                //during modes 2 and 3, otherwise 0
+                match self.read_io(STAT_ADDR) & 0b11 {
+                    2 | 3 => 0xFF,
+                    _ => 0x00,
+                }
             }
             0xFF00..=0xFF7F => {
-                //io
-                todo!()
+                self.read_io(addr)
             }
             0xFF80..=0xFFFE => {
-                //hram
-                todo!()
+                self.hram[(addr - 0xFF80) as usize]
             }
-            0xFFFF => {
-                //interrupt register
-                todo!()
+            IE_ADDR => {
+                self.interrupt_enable
             }
         }
     }
     fn write(&mut self, addr:u16, val:u8) {
-        todo!();
+        match addr {
+            0..=0x7FFF => {
+                self.cartridge.write_rom(addr, val);
+            }
+            0x8000..=0x9FFF => {
+                self.vram[self.vram_index(addr)] = val;
+            }
+            0xA000..=0xBFFF => {
+                self.cartridge.write_ram(addr, val);
+            }
+            0xC000..=0xFDFF => {
+                self.wram[self.wram_index(addr)] = val;
+            }
+            0xFE00..=0xFE9F => {
+                self.oam[(addr - 0xFE00) as usize] = val;
+            }
+            //writes to the unusable area are ignored
+            0xFEA0..=0xFEFF => {}
+            0xFF00..=0xFF7F => {
+                self.write_io(addr, val);
+            }
+            0xFF80..=0xFFFE => {
+                self.hram[(addr - 0xFF80) as usize] = val;
+            }
+            IE_ADDR => {
+                self.interrupt_enable = val;
+            }
+        }
+    }
+    fn cpu_read(&mut self, addr:u16) -> u8 {
+        self.oam_bug(addr, OamAccess::Read);
+        self.dma.conflict(addr).unwrap_or_else(|| self.read(addr))
+    }
+    fn cpu_write(&mut self, addr:u16, val:u8) {
+        self.oam_bug(addr, OamAccess::Write);
+        //the dma owns the bus, the write goes nowhere
+        if self.dma.conflict(addr).is_none() {
+            self.write(addr, val);
+        }
+    }
+    fn cpu_read_inc_dec(&mut self, addr:u16) -> u8 {
+        self.oam_bug(addr, OamAccess::ReadIncDec);
+        self.dma.conflict(addr).unwrap_or_else(|| self.read(addr))
+    }
+    fn cpu_inc_dec(&mut self, addr:u16) {
+        self.oam_bug(addr, OamAccess::Write);
+    }
+    fn oam_scan_row(&mut self, row:Option<u8>) {
+        self.oam_scan_row = row;
+    }
+    fn write_hw(&mut self, addr:u16, val:u8) {
+        match addr {
+            LCDC_ADDR..=WX_ADDR => self.lcd.write_reg(addr, val),
+            _ => self.write(addr, val),
+        }
+    }
+    fn read_vram(&self, bank:u8, addr:u16) -> u8 {
+        self.vram[bank as usize * VRAM_BANK_LEN + (addr & 0x1FFF) as usize]
+    }
+    fn bg_color(&self, palette:u8, color:u8) -> u16 {
+        self.palettes.bg_color(palette, color)
+    }
+    fn obj_color(&self, palette:u8, color:u8) -> u16 {
+        self.palettes.obj_color(palette, color)
+    }
+    fn hblank(&mut self) {
+        if self.hdma.hblank_active() {
+            self.copy_hdma_block();
+        }
+    }
+    fn speed_switch(&mut self) -> bool {
+        if !self.speed_switch_armed {
+            return false;
+        }
+        self.speed_switch_armed = false;
+        self.double_speed = !self.double_speed;
+        self.timer.set_double_speed(self.double_speed);
+        true
     }
-    fn borrow_mem(&mut self, addr:u16) -> &mut u8 {
-        todo!();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::cartridge::{test_cgb_rom, test_rom, Cartridge};
+    use crate::cpu::{CPU, IF_ADDR};
+    use crate::mem::{Bus, Mem};
+    use crate::model::Model;
+    #[test]
+    fn shared_bus() {
+        //LD A,$42; LDH ($80),A; LD A,$01; LDH ($0F),A
+        let program = [0x3E, 0x42, 0xE0, 0x80, 0x3E, 0x01, 0xE0, 0x0F];
+        let mut rom = test_rom(0x01, 0x00, 0x00);
+        rom[0x100..0x108].copy_from_slice(&program);
+        let bus = Rc::new(RefCell::new(Bus::new(Cartridge::from_rom(rom).unwrap(), Model::Dmg)));
+        let mut cpu = CPU::init(bus.clone());
+        for _ in 0..4 {
+            cpu.tick();
+        }
+        assert_eq!(bus.borrow().read(0xFF80), 0x42);
+        assert_eq!(bus.borrow().read(IF_ADDR), 0xE1);
+        //unusable area reads 0 outside of oam scan and drawing
+        assert_eq!(bus.borrow().read(0xFEA0), 0x00);
+        //the cpu can't write the mode bits
+        bus.borrow_mut().write(0xFF41, 0x03);
+        assert_eq!(bus.borrow().read(0xFF41), 0x80);
+        bus.borrow_mut().write_hw(0xFF41, 0x03);
+        assert_eq!(bus.borrow().read(0xFEFF), 0xFF);
+    }
+    #[test]
+    fn unused_io() {
+        let mut bus = Bus::new(Cartridge::from_rom(test_rom(0x00, 0x00, 0x00)).unwrap(), Model::Dmg);
+        //nothing there, or cgb only, writes don't stick
+        for addr in [0xFF03, 0xFF4C, 0xFF4D, 0xFF4F, 0xFF50, 0xFF55, 0xFF68, 0xFF6B, 0xFF70, 0xFF7F] {
+            bus.write(addr, 0x00);
+            assert_eq!(bus.read(addr), 0xFF, "{:04X}", addr);
+        }
+        //unused bits read high
+        bus.write(0xFF07, 0x00);
+        assert_eq!(bus.read(0xFF07), 0xF8);
+        bus.write(0xFF0F, 0x00);
+        assert_eq!(bus.read(0xFF0F), 0xE0);
+        bus.write(0xFF02, 0x00);
+        assert_eq!(bus.read(0xFF02), 0x7E);
+        //the ppu's registers are still plain storage
+        bus.write(0xFF4B, 0x12);
+        assert_eq!(bus.read(0xFF4B), 0x12);
+    }
+    #[test]
+    fn oam_dma() {
+        let mut bus = Bus::new(Cartridge::from_rom(test_rom(0x00, 0x00, 0x00)).unwrap(), Model::Dmg);
+        for i in 0..0xA0 {
+            bus.write(0xC000 + i, i as u8 ^ 0x55);
+        }
+        bus.write(0xFF80, 0x12);
+        bus.cpu_write(0xFF46, 0xC0);
+        assert_eq!(bus.read(0xFF46), 0xC0);
+        //one cycle of setup then one byte per cycle
+        bus.tick(4 * 3);
+        assert_eq!(bus.read(0xFE01), 0x54);
+        assert_eq!(bus.read(0xFE02), 0x00);
+        //the cpu sees the byte being copied, or 0xFF in oam
+        assert_eq!(bus.cpu_read(0xD000), 0x54);
+        assert_eq!(bus.cpu_read(0x0100), 0x54);
+        assert_eq!(bus.cpu_read(0xFE00), 0xFF);
+        assert_eq!(bus.cpu_read(0xFF80), 0x12);
+        //vram is on its own bus
+        bus.write(0x8000, 0x34);
+        assert_eq!(bus.cpu_read(0x8000), 0x34);
+        bus.cpu_write(0xC000, 0x00);
+        assert_eq!(bus.read(0xC000), 0x55);
+        for _ in 0..157 {
+            bus.tick(4);
+        }
+        assert_eq!(bus.cpu_read(0xD000), 0x9E ^ 0x55);
+        bus.tick(4);
+        assert_eq!(bus.cpu_read(0xD000), 0x00);
+        assert_eq!(bus.cpu_read(0xFE9F), 0x9F ^ 0x55);
+    }
+    #[test]
+    fn oam_bug() {
+        let oam_bus = |model| {
+            let mut bus = Bus::new(Cartridge::from_rom(test_rom(0x00, 0x00, 0x00)).unwrap(), model);
+            for i in 0..0xA0 {
+                bus.write(0xFE00 + i, i as u8);
+            }
+            bus
+        };
+        //nothing outside of mode 2
+        let mut bus = oam_bus(Model::Dmg);
+        bus.cpu_inc_dec(0xFE10);
+        assert_eq!(bus.read(0xFE10), 0x10);
+        //the row being scanned gets hit no matter which OAM address it was
+        bus.oam_scan_row(Some(2));
+        bus.cpu_inc_dec(0xC000);
+        assert_eq!(bus.read(0xFE10), 0x10);
+        bus.cpu_inc_dec(0xFEFF);
+        assert_eq!(bus.read(0xFE10), ((0x10 ^ 0x0C) & (0x08 ^ 0x0C)) ^ 0x0C);
+        assert_eq!(bus.read(0xFE17), 0x0F);
+        let mut bus = oam_bus(Model::Sgb);
+        bus.oam_scan_row(Some(3));
+        assert_eq!(bus.cpu_read(0xFE00), 0x00);
+        assert_eq!(bus.read(0xFE18), 0x10 | (0x18 & 0x14));
+        //the cgb and agb don't have it, INC rr and DEC rr included
+        for model in [Model::Cgb, Model::Agb] {
+            let mut bus = oam_bus(model);
+            bus.oam_scan_row(Some(2));
+            bus.cpu_inc_dec(0xFE10);
+            bus.cpu_read(0xFE00);
+            assert_eq!(bus.read(0xFE10), 0x10);
+        }
+    }
+    #[test]
+    fn cgb_banks() {
+        let mut bus = Bus::new(Cartridge::from_rom(test_cgb_rom(0x00, 0x00, 0x00)).unwrap(), Model::Cgb);
+        assert!(bus.cgb());
+        assert_eq!(bus.read(0xFF4F), 0xFE);
+        assert_eq!(bus.read(0xFF70), 0xF9);
+        bus.write(0x8000, 0x11);
+        bus.write(0xFF4F, 0x01);
+        assert_eq!(bus.read(0xFF4F), 0xFF);
+        assert_eq!(bus.read(0x8000), 0x00);
+        bus.write(0x8000, 0x22);
+        assert_eq!(bus.read_vram(0, 0x8000), 0x11);
+        assert_eq!(bus.read_vram(1, 0x8000), 0x22);
+        //D000 switches, C000 and its echo don't
+        bus.write(0xC000, 0x33);
+        bus.write(0xD000, 0x44);
+        bus.write(0xFF70, 0x07);
+        assert_eq!(bus.read(0xD000), 0x00);
+        bus.write(0xF000, 0x55);
+        assert_eq!(bus.read(0xD000), 0x55);
+        assert_eq!(bus.read(0xE000), 0x33);
+        //0 selects bank 1
+        bus.write(0xFF70, 0x00);
+        assert_eq!(bus.read(0xFF70), 0xF9);
+        assert_eq!(bus.read(0xD000), 0x44);
+        //palette registers
+        bus.write(0xFF68, 0x80);
+        bus.write(0xFF69, 0x1F);
+        bus.write(0xFF69, 0x00);
+        assert_eq!(bus.bg_color(0, 0), 0x001F);
+        assert_eq!(bus.read(0xFF68), 0xC2);
+        //none of it on dmg
+        let mut bus = Bus::new(Cartridge::from_rom(test_rom(0x00, 0x00, 0x00)).unwrap(), Model::Dmg);
+        bus.write(0xFF4F, 0x01);
+        bus.write(0x8000, 0x66);
+        assert_eq!(bus.read_vram(0, 0x8000), 0x66);
+        bus.write(0xFF70, 0x02);
+        bus.write(0xD000, 0x77);
+        bus.write(0xFF70, 0x03);
+        assert_eq!(bus.read(0xD000), 0x77);
+    }
+    #[test]
+    fn hdma() {
+        let mut bus = Bus::new(Cartridge::from_rom(test_cgb_rom(0x00, 0x00, 0x00)).unwrap(), Model::Cgb);
+        for i in 0..0x40 {
+            bus.write(0xC000 + i, i as u8 + 1);
+        }
+        //C000 to 8100 in bank 1
+        bus.write(0xFF4F, 1);
+        bus.write(0xFF51, 0xC0);
+        bus.write(0xFF52, 0x00);
+        bus.write(0xFF53, 0x01);
+        bus.write(0xFF54, 0x00);
+        //general purpose, 2 blocks, done by the time the write returns
+        bus.write(0xFF55, 0x01);
+        assert_eq!(bus.read_vram(1, 0x8100), 0x01);
+        assert_eq!(bus.read_vram(1, 0x811F), 0x20);
+        assert_eq!(bus.read(0xFF55), 0xFF);
+        let mut stalled = 0;
+        while bus.take_stall_cycle() {
+            stalled += 4;
+        }
+        assert_eq!(stalled, 64);
+        //hblank, picks up where the last one stopped
+        bus.write(0xFF55, 0x81);
+        assert_eq!(bus.read_vram(1, 0x8120), 0x00);
+        bus.hblank();
+        assert_eq!(bus.read_vram(1, 0x8120), 0x21);
+        assert_eq!(bus.read_vram(1, 0x8130), 0x00);
+        assert_eq!(bus.read(0xFF55), 0x00);
+        bus.hblank();
+        assert_eq!(bus.read_vram(1, 0x813F), 0x40);
+        assert_eq!(bus.read(0xFF55), 0xFF);
+        bus.hblank();
+        assert_eq!(bus.read_vram(1, 0x8140), 0x00);
     }
 }
@@ -0,0 +1,60 @@
+use crate::cartridge::{CgbSupport, Header};
+use crate::mem::{CGB_BOOT_ROM_LEN, DMG_BOOT_ROM_LEN};
+
+//which console is being emulated. they run the same cartridges but boot
+//into different states, and only the cgb and agb have color
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Model {
+    //the very first dmg revision, with its own boot rom
+    Dmg0,
+    Dmg,
+    //game boy pocket
+    Mgb,
+    Sgb,
+    Cgb,
+    //game boy advance running a game boy cartridge
+    Agb,
+}
+impl Model {
+    //a cgb if the cartridge can use one, a dmg otherwise
+    pub fn for_header(header: &Header) -> Model {
+        match header.cgb {
+            CgbSupport::None => Model::Dmg,
+            CgbSupport::Enhanced | CgbSupport::Only => Model::Cgb,
+        }
+    }
+    //the model a boot rom of this size was dumped from, the dmg family
+    //ones are all the same size
+    pub fn for_boot_rom(len: usize) -> Option<Model> {
+        match len {
+            DMG_BOOT_ROM_LEN => Some(Model::Dmg),
+            CGB_BOOT_ROM_LEN => Some(Model::Cgb),
+            _ => None,
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name {
+            "DMG0" => Some(Model::Dmg0),
+            "DMG" => Some(Model::Dmg),
+            "MGB" => Some(Model::Mgb),
+            "SGB" => Some(Model::Sgb),
+            "CGB" => Some(Model::Cgb),
+            "AGB" => Some(Model::Agb),
+            _ => None,
+        }
+    }
+    //has the color hardware, it only gets used if the cartridge asks
+    pub fn cgb(self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+    //the dmg family's ppu corrupts OAM when the cpu puts FE00-FEFF on the
+    //bus in mode 2, INC rr and DEC rr included. the cgb's cpu doesn't set it
+    //off, and the agb runs game boy cartridges on that same cpu, so its
+    //INC rr and DEC rr leave OAM alone too
+    pub fn oam_bug(self) -> bool {
+        !self.cgb()
+    }
+    pub fn boot_rom_len(self) -> usize {
+        if self.cgb() { CGB_BOOT_ROM_LEN } else { DMG_BOOT_ROM_LEN }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::dma::OAM_LEN;
+
+//OAM as the mode 2 scan sees it, 20 rows of 4 words. it reads one row
+//per M-cycle
+pub const OAM_ROWS: u8 = 20;
+const ROW_LEN: usize = 8;
+
+//how the cpu put an OAM address on the bus while the ppu was scanning
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OamAccess {
+    Read,
+    //writes, and the increment/decrement unit on its own
+    Write,
+    //a read in the same M-cycle as an increment, LD A,(HL+) and LD A,(HL-)
+    ReadIncDec,
+}
+
+//the dmg family's ppu mangles the row it's reading when the cpu's address
+//lands in FE00-FEFF, based on the first word of the row and the first and
+//third of the one before it. the rest of the row gets copied from the one
+//before. the first row never changes
+pub fn corrupt(oam: &mut [u8; OAM_LEN], row: u8, access: OamAccess) {
+    let row = row as usize;
+    if row == 0 || row >= OAM_ROWS as usize {
+        return;
+    }
+    if access == OamAccess::ReadIncDec {
+        //the rows just before get hit too, but not near either end
+        if (4..OAM_ROWS as usize - 1).contains(&row) {
+            for i in 0..2 {
+                let a = oam[(row - 2) * ROW_LEN + i];
+                let b = oam[(row - 1) * ROW_LEN + i];
+                let c = oam[row * ROW_LEN + i];
+                let d = oam[(row - 1) * ROW_LEN + 4 + i];
+                oam[(row - 1) * ROW_LEN + i] = (b & (a | c | d)) | (a & c & d);
+            }
+            oam.copy_within((row - 1) * ROW_LEN..row * ROW_LEN, row * ROW_LEN);
+            oam.copy_within((row - 1) * ROW_LEN..row * ROW_LEN, (row - 2) * ROW_LEN);
+        }
+    }
+    //bitwise, so a byte at a time does the same as a word at a time
+    for i in 0..2 {
+        let a = oam[row * ROW_LEN + i];
+        let b = oam[(row - 1) * ROW_LEN + i];
+        let c = oam[(row - 1) * ROW_LEN + 4 + i];
+        oam[row * ROW_LEN + i] = match access {
+            OamAccess::Write => ((a ^ c) & (b ^ c)) ^ c,
+            OamAccess::Read | OamAccess::ReadIncDec => b | (a & c),
+        };
+    }
+    oam.copy_within((row - 1) * ROW_LEN + 2..row * ROW_LEN, row * ROW_LEN + 2);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dma::OAM_LEN;
+    use crate::oam_bug::*;
+    //every byte holds its row number
+    fn filled_oam() -> [u8; OAM_LEN] {
+        let mut oam = [0; OAM_LEN];
+        for (i, byte) in oam.iter_mut().enumerate() {
+            *byte = (i / ROW_LEN) as u8;
+        }
+        oam
+    }
+    #[test]
+    fn corrupt_rows() {
+        let mut oam = filled_oam();
+        //a, b, c from the formulas
+        oam[5 * ROW_LEN..][..2].copy_from_slice(&[0b1100, 0b1100]);
+        oam[4 * ROW_LEN..][..2].copy_from_slice(&[0b1010, 0b1010]);
+        oam[4 * ROW_LEN + 4..][..2].copy_from_slice(&[0b0110, 0b0110]);
+        let before = oam;
+        corrupt(&mut oam, 5, OamAccess::Write);
+        //((a ^ c) & (b ^ c)) ^ c
+        assert_eq!(oam[5 * ROW_LEN..][..2], [0b1110, 0b1110]);
+        assert_eq!(oam[5 * ROW_LEN + 2..6 * ROW_LEN], before[4 * ROW_LEN + 2..5 * ROW_LEN]);
+        assert_eq!(oam[..5 * ROW_LEN], before[..5 * ROW_LEN]);
+        assert_eq!(oam[6 * ROW_LEN..], before[6 * ROW_LEN..]);
+        let mut oam = before;
+        corrupt(&mut oam, 5, OamAccess::Read);
+        //b | (a & c)
+        assert_eq!(oam[5 * ROW_LEN..][..2], [0b1110, 0b1110]);
+        let mut oam = before;
+        oam[5 * ROW_LEN] = 0b0001;
+        corrupt(&mut oam, 5, OamAccess::Read);
+        assert_eq!(oam[5 * ROW_LEN], 0b1010);
+        //the first row is left alone
+        let mut oam = before;
+        corrupt(&mut oam, 0, OamAccess::Write);
+        assert_eq!(oam, before);
+    }
+    #[test]
+    fn corrupt_read_inc_dec() {
+        let mut oam = filled_oam();
+        oam[3 * ROW_LEN] = 0b0011;
+        oam[4 * ROW_LEN] = 0b0101;
+        oam[5 * ROW_LEN] = 0b1001;
+        oam[4 * ROW_LEN + 4] = 0b0001;
+        corrupt(&mut oam, 5, OamAccess::ReadIncDec);
+        //the row before becomes (b & (a | c | d)) | (a & c & d) and is copied
+        //over both neighbours, then the usual read corruption
+        assert_eq!(oam[4 * ROW_LEN], 0b0001);
+        assert_eq!(oam[3 * ROW_LEN..4 * ROW_LEN], oam[4 * ROW_LEN..5 * ROW_LEN]);
+        assert_eq!(oam[5 * ROW_LEN], 0b0001);
+        assert_eq!(oam[5 * ROW_LEN + 1..6 * ROW_LEN], oam[4 * ROW_LEN + 1..5 * ROW_LEN]);
+        //too close to the start for the extra step
+        let mut oam = filled_oam();
+        corrupt(&mut oam, 2, OamAccess::ReadIncDec);
+        assert_eq!(oam[..ROW_LEN], [0; ROW_LEN]);
+        assert_eq!(oam[2 * ROW_LEN..3 * ROW_LEN], [1; ROW_LEN]);
+    }
+}
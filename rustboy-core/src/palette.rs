@@ -0,0 +1,118 @@
+use crate::mem::IoRegisters;
+
+pub const BCPS_ADDR: u16 = 0xFF68;
+pub const BCPD_ADDR: u16 = 0xFF69;
+pub const OCPS_ADDR: u16 = 0xFF6A;
+pub const OCPD_ADDR: u16 = 0xFF6B;
+
+//8 palettes of 4 colors, 2 bytes each
+const PALETTE_RAM_LEN: usize = 64;
+const AUTO_INCREMENT: u8 = 0x80;
+
+//one of the two cgb palette memories, reached through an index register
+//and a data register
+struct PaletteRam {
+    data: [u8; PALETTE_RAM_LEN],
+    //byte the data register points at, bit 7 moves it on after each write
+    spec: u8,
+}
+impl PaletteRam {
+    fn new() -> PaletteRam {
+        //white until something is written
+        PaletteRam {
+            data: [0xFF; PALETTE_RAM_LEN],
+            spec: 0,
+        }
+    }
+    fn index(&self) -> usize {
+        (self.spec & 0x3F) as usize
+    }
+    fn write_data(&mut self, val: u8) {
+        self.data[self.index()] = val;
+        if self.spec & AUTO_INCREMENT != 0 {
+            self.spec = AUTO_INCREMENT | ((self.spec + 1) & 0x3F);
+        }
+    }
+    //15 bit color, red in the low bits
+    fn color(&self, palette: u8, color: u8) -> u16 {
+        let i = (palette as usize * 4 + color as usize) * 2;
+        u16::from_le_bytes([self.data[i], self.data[i + 1]]) & 0x7FFF
+    }
+}
+pub struct Palettes {
+    bg: PaletteRam,
+    obj: PaletteRam,
+}
+impl Default for Palettes {
+    fn default() -> Self {
+        Palettes {
+            bg: PaletteRam::new(),
+            obj: PaletteRam::new(),
+        }
+    }
+}
+impl Palettes {
+    pub fn bg_color(&self, palette: u8, color: u8) -> u16 {
+        self.bg.color(palette, color)
+    }
+    pub fn obj_color(&self, palette: u8, color: u8) -> u16 {
+        self.obj.color(palette, color)
+    }
+}
+impl IoRegisters for Palettes {
+    fn read_reg(&self, addr:u16) -> u8 {
+        match addr {
+            //bit 6 doesn't exist
+            BCPS_ADDR => self.bg.spec | 0x40,
+            BCPD_ADDR => self.bg.data[self.bg.index()],
+            OCPS_ADDR => self.obj.spec | 0x40,
+            OCPD_ADDR => self.obj.data[self.obj.index()],
+            _ => unreachable!(),
+        }
+    }
+    fn write_reg(&mut self, addr:u16, val:u8) {
+        match addr {
+            BCPS_ADDR => self.bg.spec = val & 0xBF,
+            BCPD_ADDR => self.bg.write_data(val),
+            OCPS_ADDR => self.obj.spec = val & 0xBF,
+            OCPD_ADDR => self.obj.write_data(val),
+            _ => unreachable!(),
+        }
+    }
+}
+//a 15 bit cgb color as 0xAARRGGBB, each 5 bit channel stretched to 8 bits
+pub fn rgb555_to_argb(color: u16) -> u32 {
+    let channel = |shift: u16| {
+        let c = ((color >> shift) & 0x1F) as u32;
+        (c << 3) | (c >> 2)
+    };
+    0xff000000 | channel(0) << 16 | channel(5) << 8 | channel(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mem::IoRegisters;
+    use crate::palette::*;
+    #[test]
+    fn palette_ram() {
+        let mut palettes = Palettes::default();
+        assert_eq!(palettes.bg_color(0, 0), 0x7FFF);
+        //palette 1 color 2, auto increment
+        palettes.write_reg(BCPS_ADDR, 0x80 | 0x0C);
+        palettes.write_reg(BCPD_ADDR, 0x1F);
+        palettes.write_reg(BCPD_ADDR, 0x00);
+        assert_eq!(palettes.read_reg(BCPS_ADDR), 0xC0 | 0x0E);
+        assert_eq!(palettes.bg_color(1, 2), 0x001F);
+        assert_eq!(rgb555_to_argb(palettes.bg_color(1, 2)), 0xffff0000);
+        //no increment, and it wraps at the end
+        palettes.write_reg(OCPS_ADDR, 0x3F);
+        palettes.write_reg(OCPD_ADDR, 0x7C);
+        palettes.write_reg(OCPD_ADDR, 0x7C);
+        assert_eq!(palettes.read_reg(OCPD_ADDR), 0x7C);
+        assert_eq!(palettes.obj_color(7, 3), 0x7CFF & 0x7FFF);
+        palettes.write_reg(OCPS_ADDR, 0xBF);
+        palettes.write_reg(OCPD_ADDR, 0x00);
+        assert_eq!(palettes.read_reg(OCPS_ADDR), 0xC0);
+        assert_eq!(rgb555_to_argb(0x03E0), 0xff00ff00);
+    }
+}
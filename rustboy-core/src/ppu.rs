@@ -2,8 +2,25 @@ use bitflags::bitflags;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use crate::cpu::Interrupt;
 use crate::mem::Mem;
+use crate::palette::rgb555_to_argb;
+
+mod fifo;
+
+use fifo::PixelFifo;
+
 const LCDC_ADDR: u16 = 0xFF40;
+const STAT_ADDR: u16 = 0xFF41;
+const SCY_ADDR: u16 = 0xFF42;
+const SCX_ADDR: u16 = 0xFF43;
+const LY_ADDR: u16 = 0xFF44;
+const LYC_ADDR: u16 = 0xFF45;
+const BGP_ADDR: u16 = 0xFF47;
+const OBP0_ADDR: u16 = 0xFF48;
+const OBP1_ADDR: u16 = 0xFF49;
+const WY_ADDR:u16 = 0xFF4A;
+const WX_ADDR:u16 = 0xFF4B;
 
 const MAP_PIXEL_LEN: u32 = 256;
 const MAP_PIXEL_SIZE: u32 = 256 * 256;
@@ -14,7 +31,18 @@ const BLOCK_ZERO: u16 = 0x8000;
 const BLOCK_ONE: u16 = 0x8800;
 const BLOCK_TWO: u16 = 0x9000;
 
+const OAM_ADDR: u16 = 0xFE00;
+const OAM_ENTRIES: u16 = 40;
+const LINE_SPRITE_LIMIT: usize = 10;
+
+const WHITE: u32 = 0xffffffff;
+const LIGHT_GREY: u32 = 0xffaaaaaa;
+const DARK_GREY: u32 = 0xff555555;
+const BLACK: u32 = 0xff000000;
+const SHADES: [u32; 4] = [WHITE, LIGHT_GREY, DARK_GREY, BLACK];
+
 bitflags! {
+    #[derive(Clone, Copy)]
     struct LCDC: u8 {
         const LCD_ENABLE = 1 << 7;
         const WIN_MAP_ADDR = 1 << 6;
@@ -26,6 +54,96 @@ bitflags! {
         const PRIORITY = 1 << 0;
     }
 }
+bitflags! {
+    #[derive(Clone, Copy)]
+    struct Stat: u8 {
+        const LYC_INT = 1 << 6;
+        const OAM_INT = 1 << 5;
+        const VBLANK_INT = 1 << 4;
+        const HBLANK_INT = 1 << 3;
+        const COINCIDENCE = 1 << 2;
+        const MODE = 0b11;
+    }
+}
+bitflags! {
+    #[derive(Clone, Copy)]
+    struct ObjAttrs: u8 {
+        //bg and window colors 1-3 are drawn over the object
+        const BG_PRIORITY = 1 << 7;
+        const Y_FLIP = 1 << 6;
+        const X_FLIP = 1 << 5;
+        //OBP1 instead of OBP0
+        const PALETTE = 1 << 4;
+        //cgb only, tile data from vram bank 1
+        const BANK = 1 << 3;
+        const CGB_PALETTE = 0b111;
+        const _ = !0;
+    }
+}
+bitflags! {
+    //cgb only, a bg/window map entry's attributes are in vram bank 1
+    #[derive(Clone, Copy)]
+    struct BgAttrs: u8 {
+        //colors 1-3 are drawn over objects
+        const PRIORITY = 1 << 7;
+        const Y_FLIP = 1 << 6;
+        const X_FLIP = 1 << 5;
+        const BANK = 1 << 3;
+        const PALETTE = 0b111;
+        const _ = !0;
+    }
+}
+//an OAM entry, positions are as stored so y is offset by 16 and x by 8
+#[derive(Clone, Copy)]
+struct Sprite {
+    y: u8,
+    x: u8,
+    tile: u8,
+    attrs: ObjAttrs,
+    //position in OAM, cgb decides priority by it
+    index: u8,
+    //8 or 16, LCDC as of the scan. it can change before the object is drawn
+    height: u8,
+}
+//a bg or window pixel, palette and priority are only used on cgb
+#[derive(Clone, Copy, Default)]
+struct BgPixel {
+    color: u8,
+    palette: u8,
+    priority: bool,
+}
+//an object pixel, the palette is 0 or 1 for OBP0/OBP1 on dmg
+#[derive(Clone, Copy, Default)]
+struct ObjPixel {
+    color: u8,
+    palette: u8,
+    bg_priority: bool,
+    index: u8,
+}
+impl ObjPixel {
+    fn new(cgb: bool, sprite: Sprite, color: u8) -> ObjPixel {
+        let palette = if cgb {
+            (sprite.attrs & ObjAttrs::CGB_PALETTE).bits()
+        } else {
+            sprite.attrs.contains(ObjAttrs::PALETTE) as u8
+        };
+        ObjPixel {
+            color,
+            palette,
+            bg_priority: sprite.attrs.contains(ObjAttrs::BG_PRIORITY),
+            index: sprite.index,
+        }
+    }
+}
+//look up a 2 bit color index in a palette register
+fn shade(palette: u8, color_ind: u8) -> u32 {
+    SHADES[((palette >> (color_ind * 2)) & 0x03) as usize]
+}
+//color index of pixel x (0 is leftmost) in a row of tile data
+fn tile_pixel(low: u8, high: u8, x: u8) -> u8 {
+    let bit = 7 - x;
+    (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+}
 struct Buffer {
     pub height: u32,
     pub width: u32,
@@ -46,10 +164,6 @@ impl Buffer {
         self.data[(y as u32 * self.width + x as u32) as usize] = val;
     }
     fn write_tile(&mut self, y: u8, x: u8, palette:u8, tile: &[u16; 8]) {
-        const WHITE: u32 = 0xff000000;
-        const LIGHT_GREY: u32 = 0xff555555;
-        const DARK_GREY: u32 = 0xffaaaaaa;
-        const BLACK: u32 = 0xffffffff;
         //write to the corresponding values in the buffer
         for y0 in 0..TILE_WIDTH {
             for x0 in 0..TILE_WIDTH {
@@ -58,14 +172,7 @@ impl Buffer {
                 let palette_ind = (tile[y0 as usize] & (0x03 << x1 * 2)) >> x1 * 2;
                 debug_assert!(palette_ind <= 3);
                 //read the palette's value at the 2 bit palette_ind
-                let color_ind = (palette & (0x03 << palette_ind * 2)) >> palette_ind * 2;
-                let color = match color_ind {
-                    0b00 => WHITE,
-                    0b01 => LIGHT_GREY,
-                    0b10 => DARK_GREY,
-                    0b11 => BLACK,
-                    _ => unreachable!()
-                };
+                let color = shade(palette, palette_ind as u8);
                 let y_loc = y*(TILE_WIDTH as u8) + y0 as u8;
                 let x_loc = x*(TILE_WIDTH as u8) + x0 as u8;
                 self.set_pixel(y_loc, x_loc, color);
@@ -73,6 +180,15 @@ impl Buffer {
         }
     }
 }
+//the scanline renderer draws each line at once when mode 3 ends, which
+//is fast but mode 3 is always the same length and mid-line register
+//writes are missed. the fifo renderer draws pixel by pixel like hardware
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Renderer {
+    #[default]
+    Scanline,
+    Fifo,
+}
 #[derive(PartialEq, Eq, Debug)]
 enum Mode {
     Search,
@@ -80,15 +196,43 @@ enum Mode {
     HBlank,
     VBlank,
 }
-const SCREEN_HEIGHT:u32 = 144;
-const SCREEN_WIDTH:u32 = 160;
+impl Mode {
+    //the mode number in STAT's low bits
+    fn bits(&self) -> u8 {
+        match self {
+            Mode::HBlank => 0,
+            Mode::VBlank => 1,
+            Mode::Search => 2,
+            Mode::Draw => 3,
+        }
+    }
+}
+pub const SCREEN_HEIGHT:u32 = 144;
+pub const SCREEN_WIDTH:u32 = 160;
+const LINE_LEN: u32 = 456;
+const FRAME_LEN: u32 = 70224;
+const OAM_END: u32 = 79;
+//fixed end of mode 3 for the scanline renderer
+const DRAW_END: u32 = 251;
 pub struct PPU {
     dots: u32,
     bus: Rc<RefCell<dyn Mem>>,
     mode: Mode,
     buffer: Buffer,
+    //objects on the current line found by the mode 2 scan, in OAM order
+    line_sprites: Vec<Sprite>,
+    renderer: Renderer,
+    fifo: PixelFifo,
+    //or of the enabled STAT interrupt sources
+    stat_line: bool,
+    //color palettes, vram bank 1 attributes and OAM order object priority
+    cgb: bool,
 }
 impl PPU {
+    //the screen as 0xAARRGGBB, row by row
+    pub fn framebuffer(&self) -> &[u32] {
+        &self.buffer.data
+    }
     pub fn screen(&mut self) -> [u32; SCREEN_HEIGHT as usize * SCREEN_WIDTH as usize] {
         self.buffer.data.clone().try_into().expect("wrong size.")
     }
@@ -98,106 +242,218 @@ impl PPU {
             bus,
             mode: Mode::Search,
             buffer: Buffer::init(SCREEN_HEIGHT, SCREEN_WIDTH),
+            line_sprites: Vec::with_capacity(LINE_SPRITE_LIMIT),
+            renderer: Renderer::default(),
+            fifo: PixelFifo::new(),
+            stat_line: false,
+            cgb: false,
         }
     }
-    //advance the PPU by n CPU clocks, n*4 dots/t cycles
-    pub fn tick(&mut self, clocks: u8) {
-        const LINE_LEN: u32 = 456;
-        const FRAME_LEN: u32 = 70224;
-        const OAM_END: u32 = 79;
-        const DRAW_BEGIN: u32 = 80;
-        const DRAW_END: u32 = 251;
-        const HBLANK_BEGIN: u32 = 252;
-        const HBLANK_END: u32 = 455;
-        self.dots += clocks as u32;
-        self.dots %= FRAME_LEN;
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+    pub fn set_cgb(&mut self, cgb: bool) {
+        self.cgb = cgb;
+        self.fifo.set_cgb(cgb);
+    }
+    //advance the PPU by a number of dots. the ppu doesn't speed up in cgb
+    //double speed, so that's half the cpu's clocks there
+    pub fn tick(&mut self, dots: u8) {
+        //the fifo renderer changes mode on any dot, so go one at a time
+        for _ in 0..dots {
+            self.dot();
+        }
+    }
+    fn dot(&mut self) {
+        let lcdc = LCDC::from_bits_retain(self.bus.borrow().read(LCDC_ADDR));
+        if !lcdc.contains(LCDC::LCD_ENABLE) {
+            //the ppu sits at the start of line 0 while off, and reports mode 0.
+            //turning it back on starts a fresh frame
+            self.dots = 0;
+            self.mode = Mode::Search;
+            self.fifo.start_frame();
+            self.bus.borrow_mut().oam_scan_row(None);
+            self.update_status(false);
+            return;
+        }
         let line = self.dots / LINE_LEN;
         debug_assert!(self.dots < FRAME_LEN);
         debug_assert!(line <= 153);
-
-        match line {
-            0..=143 => {
-                //not v blank
-                if self.mode == Mode::VBlank {
-                    self.mode = Mode::Search;
+        //TODO: disable correct memory regions
+        match self.mode {
+            Mode::Search => {
+                //one row of OAM per M-cycle
+                let dot = self.dots % LINE_LEN;
+                if dot.is_multiple_of(4) {
+                    self.bus.borrow_mut().oam_scan_row(Some((dot / 4) as u8));
+                }
+                if dot == OAM_END {
+                    self.bus.borrow_mut().oam_scan_row(None);
+                    self.mode = Mode::Draw;
+                    self.oam_scan(line);
+                    let bus = self.bus.borrow();
+                    self.fifo.check_window_y(&*bus, line as u8);
+                    if self.renderer == Renderer::Fifo {
+                        self.fifo.start_line(&*bus, &self.line_sprites);
+                    }
                 }
             }
-            144..=153 => {
-                //v blank
-                debug_assert!(self.mode != Mode::Search);
-                debug_assert!(self.mode != Mode::Draw);
+            Mode::Draw => {
+                match self.renderer {
+                    Renderer::Scanline => {
+                        if self.dots % LINE_LEN == DRAW_END {
+                            self.draw_line(line);
+                        }
+                    }
+                    Renderer::Fifo => {
+                        let bus = self.bus.borrow();
+                        if let Some((x, color)) = self.fifo.dot(&*bus, line as u8) {
+                            self.buffer.set_pixel(line as u8, x, color);
+                        }
+                        if self.fifo.done() {
+                            self.fifo.end_line();
+                            self.mode = Mode::HBlank;
+                        }
+                    }
+                }
                 if self.mode == Mode::HBlank {
-                    //TODO: transition into vblank
-                    self.mode = Mode::VBlank;
+                    self.bus.borrow_mut().hblank();
                 }
             }
-            _ => unreachable!(),
+            Mode::HBlank | Mode::VBlank => {}
         }
-
-        //TODO: disable correct memory regions
-        //note to self this code still runs during vblank
-        match self.dots % LINE_LEN {
-            0..=OAM_END => {
-                debug_assert!(self.mode != Mode::Draw);
-                if self.mode == Mode::HBlank {
+        self.dots = (self.dots + 1) % FRAME_LEN;
+        if self.dots.is_multiple_of(LINE_LEN) {
+            match self.dots / LINE_LEN {
+                0 => {
+                    self.fifo.start_frame();
                     self.mode = Mode::Search;
                 }
-            }
-            DRAW_BEGIN..=DRAW_END => {
-                debug_assert!(self.mode != Mode::HBlank);
-                if self.mode == Mode::Search {
-                    self.mode = Mode::Draw;
-                    //do search
+                1..=143 => self.mode = Mode::Search,
+                144 => {
+                    self.mode = Mode::VBlank;
+                    self.bus.borrow_mut().request_interrupt(Interrupt::VBLANK);
                 }
+                _ => {}
             }
-            HBLANK_BEGIN..=HBLANK_END => {
-                debug_assert!(self.mode != Mode::Search);
-                if self.mode == Mode::Draw {
-                    self.draw_line(line);
+        }
+        self.update_status(true);
+    }
+    //publish LY and STAT, and request the STAT interrupt when its line goes high
+    fn update_status(&mut self, lcd_on: bool) {
+        let mut bus = self.bus.borrow_mut();
+        let ly = (self.dots / LINE_LEN) as u8;
+        let enabled = Stat::from_bits_retain(bus.read(STAT_ADDR))
+            & (Stat::LYC_INT | Stat::OAM_INT | Stat::VBLANK_INT | Stat::HBLANK_INT);
+        let coincidence = ly == bus.read(LYC_ADDR);
+        let mode = if lcd_on { self.mode.bits() } else { 0 };
+        let mut stat = enabled | Stat::from_bits_retain(mode);
+        stat.set(Stat::COINCIDENCE, coincidence);
+        bus.write_hw(LY_ADDR, ly);
+        bus.write_hw(STAT_ADDR, stat.bits());
+        //every source is ORed onto one line and only a rising edge requests
+        //the interrupt, so a source going high while another already holds
+        //the line does nothing (stat blocking)
+        let mode_source = match self.mode {
+            Mode::HBlank => Stat::HBLANK_INT,
+            Mode::VBlank => Stat::VBLANK_INT,
+            Mode::Search => Stat::OAM_INT,
+            Mode::Draw => Stat::empty(),
+        };
+        let stat_line = lcd_on
+            && ((coincidence && enabled.contains(Stat::LYC_INT)) || enabled.intersects(mode_source));
+        if stat_line && !self.stat_line {
+            bus.request_interrupt(Interrupt::STAT);
+        }
+        self.stat_line = stat_line;
+    }
+    //find the first 10 objects in OAM that overlap this line
+    fn oam_scan(&mut self, line: u32) {
+        let bus = self.bus.borrow();
+        let lcdc = LCDC::from_bits_retain(bus.read(LCDC_ADDR));
+        let height = if lcdc.contains(LCDC::OBJ_SIZE) { 16 } else { 8 };
+        self.line_sprites.clear();
+        for i in 0..OAM_ENTRIES {
+            let entry = OAM_ADDR + i * 4;
+            let y = bus.read(entry);
+            //y is offset by 16 so objects can be partly above the screen,
+            //x isn't checked, offscreen objects still count towards the limit
+            let top = y as u32;
+            if line + 16 >= top && line + 16 < top + height {
+                self.line_sprites.push(Sprite {
+                    y,
+                    x: bus.read(entry + 1),
+                    tile: bus.read(entry + 2),
+                    attrs: ObjAttrs::from_bits_retain(bus.read(entry + 3)),
+                    index: i as u8,
+                    height: height as u8,
+                });
+                if self.line_sprites.len() == LINE_SPRITE_LIMIT {
+                    break;
                 }
             }
-            _ => unreachable!(),
         }
     }
     fn draw_line(&mut self, line: u32) {
-        const SCY_ADDR: u16 = 0xFF42;
-        const SCX_ADDR: u16 = 0xFF43;
-        const WY_ADDR:u16 = 0xFF4A;
-        const WX_ADDR:u16 = 0xFF4B;
         self.mode = Mode::HBlank;
-        //draw the line
         let bus = self.bus.borrow();
-        let map_tl:(u32, u32) = (bus.read(SCY_ADDR).into(), bus.read(SCX_ADDR).into());
-        //TODO: this is slow but you know it works for now
-        //I'm recalculating both tile maps on every scan line
-        let lcdc = LCDC::from_bits(bus.read(LCDC_ADDR)).unwrap();
-        //on gb, this bit must be on to draw bg and window
-        if lcdc.contains(LCDC::PRIORITY) {
-            let bg_map = self.calculate_tilemap(true);
-            for i in 0..SCREEN_WIDTH {
-                let map_x = (map_tl.1 + i) % MAP_PIXEL_LEN;
-                let color = bg_map[((map_tl.0 + line) * MAP_PIXEL_LEN + map_x) as usize];
-                self.buffer.set_pixel(line.try_into().unwrap(), i.try_into().unwrap(), color);
+        let lcdc = LCDC::from_bits_retain(bus.read(LCDC_ADDR));
+        let line: u8 = line.try_into().unwrap();
+        let mut bg_line = [BgPixel::default(); SCREEN_WIDTH as usize];
+        //on dmg this bit must be on to draw bg and window, on cgb it only
+        //decides whether they can cover objects
+        if self.cgb || lcdc.contains(LCDC::PRIORITY) {
+            let scy = bus.read(SCY_ADDR);
+            let scx = bus.read(SCX_ADDR);
+            let bg_map = if lcdc.contains(LCDC::BG_MAP_ADDR) { 0x9C00 } else { 0x9800 };
+            for (x, pixel) in bg_line.iter_mut().enumerate() {
+                *pixel = map_pixel(&*bus, self.cgb, lcdc, bg_map, scx.wrapping_add(x as u8), scy.wrapping_add(line));
             }
-            if lcdc.contains(LCDC::WINDOW) {
-                let window_map = self.calculate_tilemap(false);
-                let line:u16 = line.try_into().unwrap();
-                let window_y = bus.read(WY_ADDR) as u16;
-                let window_x = bus.read(WX_ADDR) as u16;
-                if line >= window_y as u16 {
-                    let mut window_start = window_x as i32 - 7;
-                    if window_start < 0 {
-                        window_start = 0;
-                    }
-                    for i in window_start..SCREEN_WIDTH as i32 {
-                        let wind_line = (line - window_y) as usize;
-                        let len = MAP_PIXEL_LEN as usize;
-                        let color = window_map[wind_line * len + i as usize - window_start as usize];
-                        self.buffer.set_pixel(line.try_into().unwrap(), i.try_into().unwrap(), color);
+            //wx is offset by 7
+            let window_x = bus.read(WX_ADDR) as usize;
+            if lcdc.contains(LCDC::WINDOW) && window_x <= 166 {
+                //rows only advance on lines the window is drawn
+                if let Some(window_line) = self.fifo.start_window() {
+                    let window_map = if lcdc.contains(LCDC::WIN_MAP_ADDR) { 0x9C00 } else { 0x9800 };
+                    for (x, pixel) in bg_line.iter_mut().enumerate().skip(window_x.saturating_sub(7)) {
+                        let map_x = (x + 7 - window_x) as u8;
+                        *pixel = map_pixel(&*bus, self.cgb, lcdc, window_map, map_x, window_line);
                     }
                 }
             }
         }
+        self.fifo.end_line();
+        let obj_line = if lcdc.contains(LCDC::OBJ_ENABLE) {
+            self.sprite_line(&*bus, line)
+        } else {
+            [ObjPixel::default(); SCREEN_WIDTH as usize]
+        };
+        for (x, (bg, obj)) in bg_line.into_iter().zip(obj_line).enumerate() {
+            self.buffer.set_pixel(line, x as u8, mix(&*bus, self.cgb, lcdc, bg, obj));
+        }
+    }
+    //the object pixel on top at each x, the bg can still cover it
+    fn sprite_line(&self, bus: &dyn Mem, line: u8) -> [ObjPixel; SCREEN_WIDTH as usize] {
+        //on dmg the object with the smaller x wins, ties go to the lower OAM index
+        //which the stable sort keeps. cgb only goes by OAM index
+        let mut sprites = self.line_sprites.clone();
+        if !self.cgb {
+            sprites.sort_by_key(|sprite| sprite.x);
+        }
+        //transparent pixels leave the spot to the next object
+        let mut pixels = [ObjPixel::default(); SCREEN_WIDTH as usize];
+        for sprite in sprites {
+            let (low, high) = sprite_row(bus, self.cgb, sprite, line);
+            for px in 0..8u8 {
+                let screen_x = sprite.x as i32 - 8 + px as i32;
+                if !(0..SCREEN_WIDTH as i32).contains(&screen_x) || pixels[screen_x as usize].color != 0 {
+                    continue;
+                }
+                let tile_x = if sprite.attrs.contains(ObjAttrs::X_FLIP) { 7 - px } else { px };
+                pixels[screen_x as usize] = ObjPixel::new(self.cgb, sprite, tile_pixel(low, high, tile_x));
+            }
+        }
+        pixels
     }
     pub fn debug_tiles(&self) -> [u32; TILE_WIDTH as usize * TILE_WIDTH as usize * TILE_CNT] {
         let mut out = Buffer::init(16 * 8, 24 * 8);
@@ -211,7 +467,7 @@ impl PPU {
                 merged[k as usize] = spread(bus.read(i + 2*k)) | ((spread(bus.read(i + 2*k + 1)) << 1));
             }
             let iterations:u16 = (i - BLOCK_ZERO) / 16;
-            out.write_tile((iterations / 24) as u8, (iterations % 24) as u8, 0b00011011, &merged);
+            out.write_tile((iterations / 24) as u8, (iterations % 24) as u8, 0b11100100, &merged);
             i += 16;
         }
         out.data.try_into().expect("wrong size.")
@@ -272,3 +528,377 @@ fn spread(val: u8) -> u16 {
     }
     out
 }
+//final color of a pixel from the bg/window and object pixels under it
+fn mix(bus: &dyn Mem, cgb: bool, lcdc: LCDC, bg: BgPixel, obj: ObjPixel) -> u32 {
+    //bg color 0 never covers an object, and on cgb the bg enable bit
+    //turns off the bg's priority entirely
+    let obj_on_top = obj.color != 0
+        && (bg.color == 0 || !(obj.bg_priority || bg.priority) || (cgb && !lcdc.contains(LCDC::PRIORITY)));
+    match (cgb, obj_on_top) {
+        (true, true) => rgb555_to_argb(bus.obj_color(obj.palette, obj.color)),
+        (true, false) => rgb555_to_argb(bus.bg_color(bg.palette, bg.color)),
+        (false, true) => shade(bus.read(if obj.palette == 1 { OBP1_ADDR } else { OBP0_ADDR }), obj.color),
+        (false, false) => shade(bus.read(BGP_ADDR), bg.color),
+    }
+}
+//cgb attributes of the map entry at map_addr, nothing on dmg
+fn bg_attrs(bus: &dyn Mem, cgb: bool, map_addr: u16) -> BgAttrs {
+    if cgb {
+        BgAttrs::from_bits_retain(bus.read_vram(1, map_addr))
+    } else {
+        BgAttrs::empty()
+    }
+}
+//vram bank and address of a row of bg/window tile data, with the
+//attributes' bank and y flip applied
+fn bg_row_addr(lcdc: LCDC, tile_ind: u8, attrs: BgAttrs, row: u8) -> (u8, u16) {
+    let row = if attrs.contains(BgAttrs::Y_FLIP) { 7 - row } else { row };
+    (attrs.contains(BgAttrs::BANK) as u8, tile_data_addr(lcdc, tile_ind) + row as u16 * 2)
+}
+//pixel at (x, y) of the 256x256 map starting at map_addr
+fn map_pixel(bus: &dyn Mem, cgb: bool, lcdc: LCDC, map_addr: u16, x: u8, y: u8) -> BgPixel {
+    const MAP_WIDTH: u16 = 32;
+    let entry = map_addr + (y / 8) as u16 * MAP_WIDTH + (x / 8) as u16;
+    let attrs = bg_attrs(bus, cgb, entry);
+    let (bank, row_addr) = bg_row_addr(lcdc, bus.read_vram(0, entry), attrs, y % 8);
+    let tile_x = if attrs.contains(BgAttrs::X_FLIP) { 7 - x % 8 } else { x % 8 };
+    BgPixel {
+        color: tile_pixel(bus.read_vram(bank, row_addr), bus.read_vram(bank, row_addr + 1), tile_x),
+        palette: (attrs & BgAttrs::PALETTE).bits(),
+        priority: attrs.contains(BgAttrs::PRIORITY),
+    }
+}
+//tile data of the object's row on this line, with y flip applied
+fn sprite_row(bus: &dyn Mem, cgb: bool, sprite: Sprite, line: u8) -> (u8, u8) {
+    let mut row = line + 16 - sprite.y;
+    if sprite.attrs.contains(ObjAttrs::Y_FLIP) {
+        row = sprite.height - 1 - row;
+    }
+    //8x16 objects ignore bit 0, row 8-15 runs into the next tile
+    let tile = if sprite.height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+    let addr = BLOCK_ZERO + tile as u16 * 16 + row as u16 * 2;
+    let bank = (cgb && sprite.attrs.contains(ObjAttrs::BANK)) as u8;
+    (bus.read_vram(bank, addr), bus.read_vram(bank, addr + 1))
+}
+//start of a bg/window tile's 16 bytes, accounting for the addressing mode
+fn tile_data_addr(lcdc: LCDC, tile_ind: u8) -> u16 {
+    const TILE_BYTES:u16 = 16;
+    match tile_ind {
+        0..=127 => {
+            if lcdc.contains(LCDC::TILE_ADDR_MODE) {
+                BLOCK_ZERO + tile_ind as u16 * TILE_BYTES
+            } else {
+                BLOCK_TWO + tile_ind as u16 * TILE_BYTES
+            }
+        }
+        128..=255 => BLOCK_ONE + (tile_ind - 128) as u16 * TILE_BYTES,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::cartridge::{test_cgb_rom, Cartridge};
+    use crate::cpu::IF_ADDR;
+    use crate::mem::{Bus, FlatMem, Mem};
+    use crate::model::Model;
+    use crate::ppu::*;
+    #[test]
+    fn sprites() {
+        let mut mem = FlatMem::default();
+        //lcd, unsigned tile addressing, objects and bg on
+        mem.write(LCDC_ADDR, 0x93);
+        mem.write(0xFF47, 0xE4);
+        mem.write(0xFF48, 0xE4);
+        mem.write(0xFF49, 0x1B);
+        //tile 1 is solid color 3, tile 2 solid color 1
+        for i in 0..16 {
+            mem.write(0x8010 + i, 0xFF);
+            mem.write(0x8020 + i, if i % 2 == 0 { 0xFF } else { 0x00 });
+        }
+        //bg tile under x 32-39 on the first row of tiles
+        mem.write(0x9804, 2);
+        let mut oam = vec![
+            //(y, x, tile, attrs)
+            (16, 12, 1, 0x00),
+            //further left so it wins the overlap even with a higher index
+            (16, 10, 2, 0x10),
+            //bg color 0 doesn't hide anything
+            (16, 28, 1, 0x80),
+            (16, 40, 1, 0x80),
+        ];
+        //11 objects on line 8, the last one is over the limit
+        for k in 0..11 {
+            oam.push((24, 8 + 8 * k, 1, 0x00));
+        }
+        for (i, (y, x, tile, attrs)) in oam.into_iter().enumerate() {
+            let entry = 0xFE00 + i as u16 * 4;
+            mem.write(entry, y);
+            mem.write(entry + 1, x);
+            mem.write(entry + 2, tile);
+            mem.write(entry + 3, attrs);
+        }
+        let bus = Rc::new(RefCell::new(mem));
+        let mut ppu = PPU::init(bus.clone());
+        for _ in 0..(456 * 9 / 4) {
+            ppu.tick(4);
+        }
+        let screen = ppu.screen();
+        //both renderers agree when nothing changes mid-line
+        let mut fifo = PPU::init(bus);
+        fifo.set_renderer(Renderer::Fifo);
+        for _ in 0..(456 * 9 / 4) {
+            fifo.tick(4);
+        }
+        assert!(fifo.screen() == screen);
+        let pixel = |y: usize, x: usize| screen[y * SCREEN_WIDTH as usize + x];
+        assert_eq!(pixel(0, 1), WHITE);
+        assert_eq!(pixel(0, 2), DARK_GREY);
+        assert_eq!(pixel(0, 9), DARK_GREY);
+        assert_eq!(pixel(0, 10), BLACK);
+        assert_eq!(pixel(0, 20), BLACK);
+        //behind bg color 1
+        assert_eq!(pixel(0, 32), LIGHT_GREY);
+        assert_eq!(pixel(8, 79), BLACK);
+        assert_eq!(pixel(8, 80), WHITE);
+    }
+    //dots spent in mode 3 on each line of the first frame
+    fn mode3_lengths(ppu: &mut PPU, lines: usize) -> Vec<u32> {
+        let mut lengths = vec![0; lines];
+        while ((ppu.dots / LINE_LEN) as usize) < lines {
+            let line = (ppu.dots / LINE_LEN) as usize;
+            ppu.tick(1);
+            if ppu.mode == Mode::Draw {
+                lengths[line] += 1;
+            }
+        }
+        lengths
+    }
+    #[test]
+    fn fifo_timing() {
+        let mut mem = FlatMem::default();
+        mem.write(LCDC_ADDR, 0x93);
+        mem.write(BGP_ADDR, 0xE4);
+        //one object on line 0
+        mem.write(0xFE00, 16);
+        mem.write(0xFE01, 40);
+        let bus = Rc::new(RefCell::new(mem));
+        let mut ppu = PPU::init(bus.clone());
+        ppu.set_renderer(Renderer::Fifo);
+        let lengths = mode3_lengths(&mut ppu, 9);
+        let base = lengths[8];
+        assert_eq!(base, 172);
+        //the bg fetch under the object finishes before it's fetched
+        assert!((base + 6..=base + 12).contains(&lengths[0]), "{}", lengths[0]);
+        let mut ppu = PPU::init(bus.clone());
+        ppu.set_renderer(Renderer::Fifo);
+        bus.borrow_mut().write(SCX_ADDR, 3);
+        let lengths = mode3_lengths(&mut ppu, 9);
+        assert_eq!(lengths[8], base + 3);
+        //the fine scroll moves the object off the tile boundary
+        assert!(lengths[0] > lengths[8]);
+        //the window restarts the fetcher
+        bus.borrow_mut().write(SCX_ADDR, 0);
+        bus.borrow_mut().write(LCDC_ADDR, 0xB1);
+        bus.borrow_mut().write(WX_ADDR, 87);
+        let mut ppu = PPU::init(bus.clone());
+        ppu.set_renderer(Renderer::Fifo);
+        assert_eq!(mode3_lengths(&mut ppu, 9)[8], base + 6);
+    }
+    #[test]
+    fn fifo_mid_line_write() {
+        let mut mem = FlatMem::default();
+        mem.write(LCDC_ADDR, 0x91);
+        mem.write(BGP_ADDR, 0x00);
+        let bus = Rc::new(RefCell::new(mem));
+        let mut ppu = PPU::init(bus.clone());
+        ppu.set_renderer(Renderer::Fifo);
+        //80 dots of mode 2, 12 of fetching, then one pixel a dot
+        ppu.tick(80 + 12 + 100);
+        bus.borrow_mut().write(BGP_ADDR, 0xFF);
+        ppu.tick(255);
+        let screen = ppu.screen();
+        assert_eq!(screen[98], WHITE);
+        assert_eq!(screen[101], BLACK);
+        let mut ppu = PPU::init(bus.clone());
+        //the scanline renderer only sees the last value
+        bus.borrow_mut().write(BGP_ADDR, 0x00);
+        ppu.tick(80 + 12 + 100);
+        bus.borrow_mut().write(BGP_ADDR, 0xFF);
+        ppu.tick(255);
+        assert_eq!(ppu.screen()[98], BLACK);
+    }
+    #[test]
+    fn obj_size_mid_line() {
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut mem = FlatMem::default();
+            //lcd and 8x16 objects on, bg off
+            mem.write(LCDC_ADDR, 0x86);
+            mem.write(OBP0_ADDR, 0xE4);
+            //tile 0 is solid color 3, tile 1 blank
+            for i in 0..16 {
+                mem.write(0x8000 + i, 0xFF);
+            }
+            //y flipped, row 8 on line 8 comes from row 7 of tile 0
+            mem.write(0xFE00, 16);
+            mem.write(0xFE01, 8);
+            mem.write(0xFE03, 0x40);
+            let bus = Rc::new(RefCell::new(mem));
+            let mut ppu = PPU::init(bus.clone());
+            ppu.set_renderer(renderer);
+            for _ in 0..(456 * 8 + 80) / 4 {
+                ppu.tick(4);
+            }
+            //8x8 after the scan picked the object up
+            bus.borrow_mut().write(LCDC_ADDR, 0x82);
+            for _ in 0..456 / 4 {
+                ppu.tick(4);
+            }
+            assert_eq!(ppu.screen()[8 * SCREEN_WIDTH as usize], BLACK, "{:?}", renderer);
+        }
+    }
+    #[test]
+    fn lcd_off() {
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut mem = FlatMem::default();
+            //window over the whole screen, only tile row 0 is black
+            mem.write(LCDC_ADDR, 0xB1);
+            mem.write(BGP_ADDR, 0xE4);
+            mem.write(WX_ADDR, 7);
+            mem.write(0x8000, 0xFF);
+            mem.write(0x8001, 0xFF);
+            let bus = Rc::new(RefCell::new(mem));
+            let mut ppu = PPU::init(bus.clone());
+            ppu.set_renderer(renderer);
+            ppu.tick(255);
+            ppu.tick(255);
+            assert_eq!(ppu.screen()[0], BLACK);
+            for _ in 0..10 * 2 {
+                ppu.tick(228);
+            }
+            //off and on again in the middle of the frame, the window starts
+            //over from its first row
+            bus.borrow_mut().write(LCDC_ADDR, 0x31);
+            ppu.tick(100);
+            bus.borrow_mut().write(LCDC_ADDR, 0xB1);
+            ppu.tick(255);
+            ppu.tick(255);
+            assert_eq!(ppu.screen()[0], BLACK, "{:?}", renderer);
+            assert_eq!(ppu.screen()[160 * 2], WHITE, "{:?}", renderer);
+        }
+    }
+    //run n dots, counting the interrupts requested and clearing IF each time
+    fn count_interrupts(ppu: &mut PPU, bus: &Rc<RefCell<FlatMem>>, dots: u32) -> (u32, u32) {
+        let (mut vblank, mut stat) = (0, 0);
+        for _ in 0..dots {
+            ppu.tick(1);
+            let flags = Interrupt::from_bits_retain(bus.borrow().read(IF_ADDR));
+            vblank += flags.contains(Interrupt::VBLANK) as u32;
+            stat += flags.contains(Interrupt::STAT) as u32;
+            bus.borrow_mut().write(IF_ADDR, 0);
+        }
+        (vblank, stat)
+    }
+    #[test]
+    fn stat() {
+        let mut mem = FlatMem::default();
+        mem.write(LCDC_ADDR, 0x91);
+        mem.write(LYC_ADDR, 2);
+        mem.write(STAT_ADDR, Stat::HBLANK_INT.bits());
+        let bus = Rc::new(RefCell::new(mem));
+        let mut ppu = PPU::init(bus.clone());
+        assert_eq!(count_interrupts(&mut ppu, &bus, 456 * 4), (0, 4));
+        assert_eq!(bus.borrow().read(LY_ADDR), 4);
+        assert_eq!(bus.borrow().read(STAT_ADDR) & 0x07, 2);
+        //enabling mode 2 during mode 2 raises the line, after that mode 2
+        //right after mode 0 is blocked since the line never drops
+        bus.borrow_mut().write(STAT_ADDR, (Stat::HBLANK_INT | Stat::OAM_INT).bits());
+        assert_eq!(count_interrupts(&mut ppu, &bus, 456 * 4), (0, 5));
+        //line 2 matches LYC but mode 2 is already holding the line
+        let mut ppu = PPU::init(bus.clone());
+        bus.borrow_mut().write(STAT_ADDR, (Stat::LYC_INT | Stat::OAM_INT).bits());
+        assert_eq!(count_interrupts(&mut ppu, &bus, 456 * 3), (0, 3));
+        let mut ppu = PPU::init(bus.clone());
+        bus.borrow_mut().write(STAT_ADDR, Stat::LYC_INT.bits());
+        assert_eq!(count_interrupts(&mut ppu, &bus, 456 * 2 + 10), (0, 1));
+        assert_eq!(bus.borrow().read(STAT_ADDR) & 0x04, 0x04);
+        //vblank fires once, at the start of line 144
+        let (vblank, _) = count_interrupts(&mut ppu, &bus, 456 * 142 - 10);
+        assert_eq!((vblank, bus.borrow().read(LY_ADDR)), (1, 144));
+        assert_eq!(bus.borrow().read(STAT_ADDR) & 0x03, 1);
+        //while off the ppu stays on line 0 in mode 0
+        bus.borrow_mut().write(LCDC_ADDR, 0x11);
+        assert_eq!(count_interrupts(&mut ppu, &bus, 456), (0, 0));
+        assert_eq!(bus.borrow().read(LY_ADDR), 0);
+        assert_eq!(bus.borrow().read(STAT_ADDR) & 0x03, 0);
+    }
+    #[test]
+    fn cgb() {
+        let mut bus = Bus::new(Cartridge::from_rom(test_cgb_rom(0x00, 0x00, 0x00)).unwrap(), Model::Cgb);
+        bus.write(LCDC_ADDR, 0x93);
+        //(palette, color, 15 bit color) for bg then objects
+        for (spec, data, palette, color, rgb) in [
+            (0xFF68, 0xFF69, 0, 0, 0x001Fu16),
+            (0xFF68, 0xFF69, 2, 3, 0x03E0),
+            (0xFF6A, 0xFF6B, 5, 3, 0x7C00),
+            (0xFF6A, 0xFF6B, 6, 3, 0x7C1F),
+        ] {
+            bus.write(spec, 0x80 | (palette * 8 + color * 2));
+            bus.write(data, rgb as u8);
+            bus.write(data, (rgb >> 8) as u8);
+        }
+        //tile 2 in bank 0 has a color 3 pixel at the left of each row
+        for i in 0..16 {
+            bus.write(0x8020 + i, 0x80);
+        }
+        //tile 1 is only solid color 3 in bank 1
+        bus.write(0xFF4F, 1);
+        for i in 0..16 {
+            bus.write(0x8010 + i, 0xFF);
+        }
+        //attributes: palette 2 from bank 1, the same over the objects,
+        //then palette 2 flipped
+        bus.write(0x9800, 0x0A);
+        bus.write(0x9801, 0x8A);
+        bus.write(0x9802, 0x22);
+        bus.write(0xFF4F, 0);
+        bus.write(0x9800, 1);
+        bus.write(0x9801, 1);
+        bus.write(0x9802, 2);
+        for (i, (x, attrs)) in [(16, 0x0D), (48, 0x0D), (44, 0x0E)].into_iter().enumerate() {
+            let entry = 0xFE00 + i as u16 * 4;
+            bus.write(entry, 16);
+            bus.write(entry + 1, x);
+            bus.write(entry + 2, 1);
+            bus.write(entry + 3, attrs);
+        }
+        let bus = Rc::new(RefCell::new(bus));
+        let mut screens = Vec::new();
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut ppu = PPU::init(bus.clone());
+            ppu.set_cgb(true);
+            ppu.set_renderer(renderer);
+            for _ in 0..456 / 4 {
+                ppu.tick(4);
+            }
+            screens.push(ppu.screen());
+        }
+        assert!(screens[0] == screens[1]);
+        let pixel = |x: usize| screens[0][x];
+        const RED: u32 = 0xffff0000;
+        const GREEN: u32 = 0xff00ff00;
+        const BLUE: u32 = 0xff0000ff;
+        assert_eq!(pixel(0), GREEN);
+        //bg priority covers the object
+        assert_eq!(pixel(8), GREEN);
+        //x flip
+        assert_eq!(pixel(16), WHITE);
+        assert_eq!(pixel(23), GREEN);
+        assert_eq!(pixel(32), RED);
+        //the lower OAM index wins even though the other is further left
+        assert_eq!(pixel(36), 0xffff00ff);
+        assert_eq!(pixel(40), BLUE);
+        assert_eq!(pixel(47), BLUE);
+    }
+}
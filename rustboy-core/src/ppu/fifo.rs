@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+
+use crate::mem::Mem;
+use crate::ppu::{
+    bg_attrs, bg_row_addr, mix, sprite_row, tile_pixel, BgAttrs, BgPixel, ObjAttrs, ObjPixel, Sprite, LCDC, LCDC_ADDR,
+    SCREEN_WIDTH, SCX_ADDR, SCY_ADDR, WX_ADDR, WY_ADDR,
+};
+
+//dots an object fetch stalls the pipeline once the bg fetcher is ready
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum FetchStep {
+    Tile,
+    Low,
+    High,
+    //waits until the bg fifo is empty
+    Push,
+}
+//mode 3 as a background fetcher feeding a bg fifo, with object fetches
+//pausing it and mixing into a separate object fifo
+pub struct PixelFifo {
+    //pixels pushed to the screen this line
+    x: u8,
+    //pixels to throw away before drawing, scx % 8 or the part of the window left of the screen
+    discard: u8,
+    bg: VecDeque<BgPixel>,
+    obj: VecDeque<ObjPixel>,
+    step: FetchStep,
+    //every step but push takes 2 dots
+    step_dot: u8,
+    //tile column being fetched, relative to the start of the bg or window
+    fetch_x: u8,
+    tile: u8,
+    attrs: BgAttrs,
+    low: u8,
+    high: u8,
+    //the first fetch of a line is thrown away
+    first_fetch: bool,
+    in_window: bool,
+    //window rows drawn so far this frame, so hiding it doesn't skip rows
+    window_line: u8,
+    //set once LY has matched WY this frame
+    window_y: bool,
+    //objects on the line not fetched yet, sorted by x
+    sprites: Vec<Sprite>,
+    //object being fetched and the dots it has left
+    sprite_fetch: Option<(Sprite, u8)>,
+    cgb: bool,
+}
+impl PixelFifo {
+    pub fn new() -> PixelFifo {
+        PixelFifo {
+            x: 0,
+            discard: 0,
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+            step: FetchStep::Tile,
+            step_dot: 0,
+            fetch_x: 0,
+            tile: 0,
+            attrs: BgAttrs::empty(),
+            low: 0,
+            high: 0,
+            first_fetch: true,
+            in_window: false,
+            window_line: 0,
+            window_y: false,
+            sprites: Vec::new(),
+            sprite_fetch: None,
+            cgb: false,
+        }
+    }
+    pub fn set_cgb(&mut self, cgb: bool) {
+        self.cgb = cgb;
+    }
+    pub fn start_frame(&mut self) {
+        self.window_line = 0;
+        self.window_y = false;
+    }
+    //the window can only start on lines after LY has matched WY, both
+    //renderers need this checked every line
+    pub fn check_window_y(&mut self, bus: &dyn Mem, line: u8) {
+        if bus.read(WY_ADDR) == line {
+            self.window_y = true;
+        }
+    }
+    pub fn start_line(&mut self, bus: &dyn Mem, sprites: &[Sprite]) {
+        self.x = 0;
+        //scx's fine scroll is only read here, the coarse part on every tile fetch
+        self.discard = bus.read(SCX_ADDR) % 8;
+        self.bg.clear();
+        self.obj.clear();
+        self.step = FetchStep::Tile;
+        self.step_dot = 0;
+        self.fetch_x = 0;
+        self.first_fetch = true;
+        self.in_window = false;
+        //stable, so ties stay in OAM order
+        self.sprites = sprites.to_vec();
+        self.sprites.sort_by_key(|sprite| sprite.x);
+        self.sprite_fetch = None;
+    }
+    //the scanline renderer draws the window all at once but shares this
+    //frame's window state, returns the window row to draw if it's triggered
+    pub fn start_window(&mut self) -> Option<u8> {
+        if !self.window_y {
+            return None;
+        }
+        self.in_window = true;
+        Some(self.window_line)
+    }
+    pub fn end_line(&mut self) {
+        if self.in_window {
+            self.window_line = self.window_line.wrapping_add(1);
+            self.in_window = false;
+        }
+    }
+    pub fn done(&self) -> bool {
+        self.x as u32 == SCREEN_WIDTH
+    }
+    //run one dot of mode 3, returning the pixel drawn if there was one
+    pub fn dot(&mut self, bus: &dyn Mem, line: u8) -> Option<(u8, u32)> {
+        let lcdc = LCDC::from_bits_retain(bus.read(LCDC_ADDR));
+        if let Some((sprite, left)) = &mut self.sprite_fetch {
+            *left -= 1;
+            if *left == 0 {
+                let sprite = *sprite;
+                self.sprite_fetch = None;
+                self.merge_sprite(bus, line, sprite);
+            }
+            return None;
+        }
+        let sprite_hit = lcdc.contains(LCDC::OBJ_ENABLE)
+            && self.sprites.first().is_some_and(|sprite| sprite.x <= self.x + 8);
+        if sprite_hit {
+            //the bg fetch in progress finishes first, which is where the
+            //variable part of the object penalty comes from
+            if self.step != FetchStep::Push || self.bg.is_empty() {
+                self.fetch(bus, lcdc, line);
+            }
+            if self.step == FetchStep::Push && !self.bg.is_empty() {
+                //this dot is the first of the fetch
+                let sprite = self.sprites.remove(0);
+                self.sprite_fetch = Some((sprite, SPRITE_FETCH_DOTS - 1));
+            }
+            return None;
+        }
+        //on dmg, the bg enable bit also hides the window
+        let window_x = bus.read(WX_ADDR);
+        if !self.in_window
+            && lcdc.contains(LCDC::WINDOW)
+            && (self.cgb || lcdc.contains(LCDC::PRIORITY))
+            && self.window_y
+            && window_x <= 166
+            && self.x + 7 >= window_x
+        {
+            //restart the fetcher on the window's first tile
+            self.in_window = true;
+            self.bg.clear();
+            self.step = FetchStep::Tile;
+            self.step_dot = 0;
+            self.fetch_x = 0;
+            self.discard = 7u8.saturating_sub(window_x);
+        }
+        self.fetch(bus, lcdc, line);
+        let bg = self.bg.pop_front()?;
+        if self.discard > 0 {
+            self.discard -= 1;
+            return None;
+        }
+        let obj = self.obj.pop_front().unwrap_or_default();
+        let bg = if self.cgb || lcdc.contains(LCDC::PRIORITY) { bg } else { BgPixel::default() };
+        let obj = if lcdc.contains(LCDC::OBJ_ENABLE) { obj } else { ObjPixel::default() };
+        let x = self.x;
+        self.x += 1;
+        Some((x, mix(bus, self.cgb, lcdc, bg, obj)))
+    }
+    //map address and row within the tile the fetcher is on, registers are
+    //read again on every step so mid-line writes land on the next tile
+    fn fetch_pos(&self, bus: &dyn Mem, lcdc: LCDC, line: u8) -> (u16, u8) {
+        const MAP_WIDTH: u16 = 32;
+        let (map_select, map_x, y) = if self.in_window {
+            (LCDC::WIN_MAP_ADDR, self.fetch_x, self.window_line)
+        } else {
+            let map_x = (bus.read(SCX_ADDR) / 8).wrapping_add(self.fetch_x) % 32;
+            (LCDC::BG_MAP_ADDR, map_x, line.wrapping_add(bus.read(SCY_ADDR)))
+        };
+        let map_addr = if lcdc.contains(map_select) { 0x9C00 } else { 0x9800 };
+        (map_addr + (y / 8) as u16 * MAP_WIDTH + map_x as u16, y % 8)
+    }
+    fn fetch(&mut self, bus: &dyn Mem, lcdc: LCDC, line: u8) {
+        if self.step == FetchStep::Push {
+            if self.bg.is_empty() {
+                for px in 0..8 {
+                    let tile_x = if self.attrs.contains(BgAttrs::X_FLIP) { 7 - px } else { px };
+                    self.bg.push_back(BgPixel {
+                        color: tile_pixel(self.low, self.high, tile_x),
+                        palette: (self.attrs & BgAttrs::PALETTE).bits(),
+                        priority: self.attrs.contains(BgAttrs::PRIORITY),
+                    });
+                }
+                self.fetch_x = self.fetch_x.wrapping_add(1);
+                self.step = FetchStep::Tile;
+            }
+            return;
+        }
+        self.step_dot += 1;
+        if self.step_dot < 2 {
+            return;
+        }
+        self.step_dot = 0;
+        let (map_addr, row) = self.fetch_pos(bus, lcdc, line);
+        let (bank, row_addr) = bg_row_addr(lcdc, self.tile, self.attrs, row);
+        match self.step {
+            FetchStep::Tile => {
+                self.tile = bus.read_vram(0, map_addr);
+                self.attrs = bg_attrs(bus, self.cgb, map_addr);
+                self.step = FetchStep::Low;
+            }
+            FetchStep::Low => {
+                self.low = bus.read_vram(bank, row_addr);
+                self.step = FetchStep::High;
+            }
+            FetchStep::High => {
+                self.high = bus.read_vram(bank, row_addr + 1);
+                self.step = if self.first_fetch { FetchStep::Tile } else { FetchStep::Push };
+                self.first_fetch = false;
+            }
+            FetchStep::Push => unreachable!(),
+        }
+    }
+    //mix an object's row into the object fifo. on dmg pixels already there
+    //from an earlier object keep priority unless they're transparent, on
+    //cgb the lower OAM index wins
+    fn merge_sprite(&mut self, bus: &dyn Mem, line: u8, sprite: Sprite) {
+        let (low, high) = sprite_row(bus, self.cgb, sprite, line);
+        while self.obj.len() < 8 {
+            self.obj.push_back(ObjPixel::default());
+        }
+        for px in 0..8u8 {
+            //objects left of x 8 are cut off at the screen edge
+            let offset = sprite.x as i32 - 8 + px as i32 - self.x as i32;
+            if offset < 0 {
+                continue;
+            }
+            let tile_x = if sprite.attrs.contains(ObjAttrs::X_FLIP) { 7 - px } else { px };
+            let pixel = ObjPixel::new(self.cgb, sprite, tile_pixel(low, high, tile_x));
+            let slot = &mut self.obj[offset as usize];
+            if slot.color == 0 || (self.cgb && pixel.color != 0 && pixel.index < slot.index) {
+                *slot = pixel;
+            }
+        }
+    }
+}
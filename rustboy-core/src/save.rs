@@ -0,0 +1,95 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::cartridge::Cartridge;
+
+//saves live next to the rom with a .sav extension
+pub fn save_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sav")
+}
+//returns false if there was no save to load
+pub fn load(cart: &mut Cartridge, path: &Path) -> io::Result<bool> {
+    if !cart.has_battery() {
+        return Ok(false);
+    }
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    cart.load_save_data(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(true)
+}
+//write to a temporary file first so a crash mid write can't eat the old save
+fn write(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("sav.tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)
+}
+
+//periodically writes battery backed ram to disk, frontends poll it once a frame
+pub struct Autosave {
+    path: PathBuf,
+    interval: Duration,
+    last_save: Instant,
+    //what's on disk, so unchanged ram isn't rewritten
+    saved: Option<Vec<u8>>,
+}
+impl Autosave {
+    pub fn new(path: PathBuf, interval: Duration) -> Autosave {
+        Autosave {
+            path,
+            interval,
+            last_save: Instant::now(),
+            saved: None,
+        }
+    }
+    //returns whether anything was written
+    pub fn poll(&mut self, cart: &Cartridge) -> io::Result<bool> {
+        if self.last_save.elapsed() < self.interval {
+            return Ok(false);
+        }
+        self.flush(cart)
+    }
+    //save right away if anything changed, call this on exit
+    pub fn flush(&mut self, cart: &Cartridge) -> io::Result<bool> {
+        self.last_save = Instant::now();
+        let data = match cart.save_data() {
+            Some(data) => data,
+            None => return Ok(false),
+        };
+        if self.saved.as_ref() == Some(&data) {
+            return Ok(false);
+        }
+        write(&self.path, &data)?;
+        self.saved = Some(data);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::cartridge::{test_rom, Cartridge};
+    use crate::save::{load, save_path, Autosave};
+    #[test]
+    fn autosave() {
+        let dir = std::env::temp_dir().join(format!("rustboy-save-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = save_path(&dir.join("game.gb"));
+        let mut cart = Cartridge::from_rom(test_rom(0x03, 0x01, 0x02)).unwrap();
+        let mut autosave = Autosave::new(path.clone(), Duration::from_secs(60));
+        cart.write_rom(0x0000, 0x0A);
+        cart.write_ram(0xA000, 0x99);
+        assert!(!autosave.poll(&cart).unwrap(), "interval hasn't passed yet");
+        assert!(autosave.flush(&cart).unwrap());
+        assert!(!autosave.flush(&cart).unwrap(), "ram didn't change");
+
+        let mut reloaded = Cartridge::from_rom(test_rom(0x03, 0x01, 0x02)).unwrap();
+        assert!(load(&mut reloaded, &path).unwrap());
+        reloaded.write_rom(0x0000, 0x0A);
+        assert_eq!(reloaded.read_ram(0xA000), 0x99);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
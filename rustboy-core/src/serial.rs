@@ -0,0 +1,95 @@
+use crate::mem::IoRegisters;
+
+pub const SB_ADDR: u16 = 0xFF01;
+pub const SC_ADDR: u16 = 0xFF02;
+
+const TRANSFER_START: u8 = 0x80;
+const INTERNAL_CLOCK: u8 = 0x01;
+//the internal clock runs at 8192 Hz
+const CLOCKS_PER_BIT: u32 = 512;
+
+//there's never anything on the other end of the cable, so bits shifted in
+//are all 1s and transfers on the external clock never finish
+#[derive(Default)]
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    bits_left: u8,
+    timer: u32,
+    //bytes sent since the last take_output
+    output: Vec<u8>,
+}
+impl Serial {
+    //everything the game has sent, test roms print their results this way
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+    //advance by n t cycles, true if a transfer finished and requested the interrupt
+    pub fn tick(&mut self, clocks: u8) -> bool {
+        if self.bits_left == 0 {
+            return false;
+        }
+        self.timer += clocks as u32;
+        while self.timer >= CLOCKS_PER_BIT && self.bits_left > 0 {
+            self.timer -= CLOCKS_PER_BIT;
+            self.sb = (self.sb << 1) | 1;
+            self.bits_left -= 1;
+        }
+        if self.bits_left == 0 {
+            self.sc &= !TRANSFER_START;
+            return true;
+        }
+        false
+    }
+}
+impl IoRegisters for Serial {
+    fn read_reg(&self, addr:u16) -> u8 {
+        match addr {
+            SB_ADDR => self.sb,
+            //only the start and clock select bits exist on dmg
+            SC_ADDR => self.sc | 0x7E,
+            _ => unreachable!(),
+        }
+    }
+    fn write_reg(&mut self, addr:u16, val:u8) {
+        match addr {
+            SB_ADDR => self.sb = val,
+            SC_ADDR => {
+                self.sc = val & (TRANSFER_START | INTERNAL_CLOCK);
+                if val & TRANSFER_START != 0 && val & INTERNAL_CLOCK != 0 {
+                    self.output.push(self.sb);
+                    self.bits_left = 8;
+                    self.timer = 0;
+                } else {
+                    self.bits_left = 0;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mem::IoRegisters;
+    use crate::serial::*;
+    #[test]
+    fn transfer() {
+        let mut serial = Serial::default();
+        serial.write_reg(SB_ADDR, b'P');
+        serial.write_reg(SC_ADDR, 0x81);
+        assert_eq!(serial.read_reg(SC_ADDR), 0xFF);
+        //8 bits at 512 clocks each
+        for _ in 0..4095 / 4 {
+            assert!(!serial.tick(4));
+        }
+        assert!(serial.tick(4));
+        assert_eq!(serial.read_reg(SC_ADDR), 0x7F);
+        assert_eq!(serial.read_reg(SB_ADDR), 0xFF);
+        assert_eq!(serial.take_output(), b"P");
+        //nothing drives the external clock
+        serial.write_reg(SC_ADDR, 0x80);
+        assert!(!serial.tick(255));
+        assert!(serial.take_output().is_empty());
+    }
+}
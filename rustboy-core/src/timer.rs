@@ -0,0 +1,193 @@
+use crate::mem::IoRegisters;
+
+pub const DIV_ADDR: u16 = 0xFF04;
+pub const TIMA_ADDR: u16 = 0xFF05;
+pub const TMA_ADDR: u16 = 0xFF06;
+pub const TAC_ADDR: u16 = 0xFF07;
+
+const TAC_ENABLE: u8 = 0x04;
+const M_CYCLE: u16 = 4;
+//DIV bit 4, the apu's frame sequencer steps when it falls. bit 5 in
+//double speed, so it keeps the same rate
+const DIV_APU_BIT: u16 = 1 << 12;
+
+#[derive(Default)]
+pub struct Timer {
+    //DIV is the upper byte of this counter, which goes up every t cycle
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    //TIMA overflowed last cycle, it reads 0 for one cycle before the reload
+    overflow: bool,
+    //TMA was copied into TIMA this cycle, TIMA writes lose to it and TMA
+    //writes go through to TIMA as well
+    reloading: bool,
+    //frame sequencer steps owed to the apu
+    apu_steps: u8,
+    double_speed: bool,
+}
+impl Timer {
+    //the counter bit TAC selects, ANDed with the enable bit. TIMA goes up
+    //when this falls, which is also why DIV and TAC writes can bump it
+    fn signal(&self) -> bool {
+        const TAC_BITS: [u16; 4] = [9, 3, 5, 7];
+        let bit = TAC_BITS[(self.tac & 0x03) as usize];
+        self.tac & TAC_ENABLE != 0 && self.counter & (1 << bit) != 0
+    }
+    fn increment(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        self.overflow = overflow;
+    }
+    //the boot rom runs for a while before the cartridge starts, skipping it
+    //has to leave DIV where it would have been
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        self.double_speed = double_speed;
+    }
+    fn apu_bit(&self) -> u16 {
+        if self.double_speed {
+            DIV_APU_BIT << 1
+        } else {
+            DIV_APU_BIT
+        }
+    }
+    //frame sequencer steps since the last call
+    pub fn take_apu_steps(&mut self) -> u8 {
+        std::mem::take(&mut self.apu_steps)
+    }
+    //advance by n t cycles, true if the timer interrupt was requested
+    pub fn tick(&mut self, clocks: u8) -> bool {
+        let mut interrupt = false;
+        for _ in 0..(clocks as u16).div_ceil(M_CYCLE) {
+            self.reloading = false;
+            if self.overflow {
+                self.overflow = false;
+                self.tima = self.tma;
+                self.reloading = true;
+                interrupt = true;
+            }
+            let before = self.signal();
+            let old = self.counter;
+            self.counter = self.counter.wrapping_add(M_CYCLE);
+            if before && !self.signal() {
+                self.increment();
+            }
+            if old & self.apu_bit() != 0 && self.counter & self.apu_bit() == 0 {
+                self.apu_steps += 1;
+            }
+        }
+        interrupt
+    }
+}
+impl IoRegisters for Timer {
+    fn read_reg(&self, addr:u16) -> u8 {
+        match addr {
+            DIV_ADDR => (self.counter >> 8) as u8,
+            TIMA_ADDR => self.tima,
+            TMA_ADDR => self.tma,
+            //upper 5 bits are unused
+            TAC_ADDR => self.tac | 0xF8,
+            _ => unreachable!(),
+        }
+    }
+    fn write_reg(&mut self, addr:u16, val:u8) {
+        let before = self.signal();
+        match addr {
+            DIV_ADDR => {
+                if self.counter & self.apu_bit() != 0 {
+                    self.apu_steps += 1;
+                }
+                self.counter = 0;
+            }
+            TIMA_ADDR => {
+                if !self.reloading {
+                    //writing during the delay cycle cancels the reload and interrupt
+                    self.overflow = false;
+                    self.tima = val;
+                }
+            }
+            TMA_ADDR => {
+                self.tma = val;
+                if self.reloading {
+                    self.tima = val;
+                }
+            }
+            TAC_ADDR => self.tac = val & 0x07,
+            _ => unreachable!(),
+        }
+        //resetting the counter or switching bits can cause a falling edge
+        if before && !self.signal() {
+            self.increment();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mem::IoRegisters;
+    use crate::timer::*;
+    #[test]
+    fn falling_edge() {
+        let mut timer = Timer::default();
+        //262144 Hz, bit 3, every 16 t cycles
+        timer.write_reg(TAC_ADDR, 0x05);
+        timer.tick(64);
+        assert_eq!(timer.read_reg(TIMA_ADDR), 4);
+        //bit 3 is set after 8 more cycles, resetting DIV makes it fall
+        timer.tick(8);
+        timer.write_reg(DIV_ADDR, 0x12);
+        assert_eq!(timer.read_reg(DIV_ADDR), 0);
+        assert_eq!(timer.read_reg(TIMA_ADDR), 5);
+        //so does switching to a bit that's clear, or disabling the timer
+        timer.tick(8);
+        timer.write_reg(TAC_ADDR, 0x06);
+        assert_eq!(timer.read_reg(TIMA_ADDR), 6);
+        timer.write_reg(TAC_ADDR, 0x05);
+        timer.write_reg(TAC_ADDR, 0x01);
+        assert_eq!(timer.read_reg(TIMA_ADDR), 7);
+        assert_eq!(timer.read_reg(TAC_ADDR), 0xF9);
+    }
+    #[test]
+    fn overflow() {
+        let mut timer = Timer::default();
+        timer.write_reg(TAC_ADDR, 0x05);
+        timer.write_reg(TMA_ADDR, 0xAB);
+        timer.write_reg(TIMA_ADDR, 0xFF);
+        assert!(!timer.tick(16));
+        //TIMA is 0 for a cycle before TMA gets loaded
+        assert_eq!(timer.read_reg(TIMA_ADDR), 0);
+        assert!(timer.tick(4));
+        assert_eq!(timer.read_reg(TIMA_ADDR), 0xAB);
+        //TIMA writes in the reload cycle are ignored, TMA writes go through
+        timer.write_reg(TIMA_ADDR, 0x10);
+        timer.write_reg(TMA_ADDR, 0xCD);
+        assert_eq!(timer.read_reg(TIMA_ADDR), 0xCD);
+        //a TIMA write in the delay cycle cancels the reload
+        timer.tick(4);
+        timer.write_reg(TIMA_ADDR, 0xFF);
+        timer.tick(8);
+        assert_eq!(timer.read_reg(TIMA_ADDR), 0);
+        timer.write_reg(TIMA_ADDR, 0x20);
+        assert!(!timer.tick(4));
+        assert_eq!(timer.read_reg(TIMA_ADDR), 0x20);
+    }
+    #[test]
+    fn apu_steps() {
+        //512 Hz at either speed, every 8192 t cycles at normal speed and
+        //16384 cpu t cycles at double speed
+        let mut timer = Timer::default();
+        for _ in 0..16384 / 4 {
+            timer.tick(4);
+        }
+        assert_eq!(timer.take_apu_steps(), 2);
+        timer.set_double_speed(true);
+        for _ in 0..16384 / 4 {
+            timer.tick(4);
+        }
+        assert_eq!(timer.take_apu_steps(), 1);
+    }
+}
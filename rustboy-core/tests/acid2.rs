@@ -0,0 +1,81 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use rustboy_core::{Config, GameBoy, Renderer, FRAME_CLOCKS, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+//dmg-acid2 draws a face using the window, object priority and 8x16 objects
+//then runs LD B,B. any ppu mistake shows up as a difference from the
+//reference screenshot, README.md in the suite lists what each one means
+const ROM: &str = "test_data/dmg-acid2.gb";
+const REFERENCE: &str = "test_data/dmg-acid2.png";
+const FRAME_LIMIT: u32 = 60;
+//mismatched pixels in the diff image, everything else is faded
+const DIFF_COLOR: [u8; 3] = [0xff, 0x00, 0x00];
+
+fn data_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(name)
+}
+fn read_png(path: &Path) -> Vec<u8> {
+    let decoder = png::Decoder::new(File::open(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e)));
+    let mut reader = decoder.read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).unwrap();
+    assert_eq!((info.width, info.height), (SCREEN_WIDTH, SCREEN_HEIGHT));
+    assert_eq!(info.color_type, png::ColorType::Rgb);
+    data.truncate(info.buffer_size());
+    data
+}
+fn write_png(path: &Path, data: &[u8]) {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path).unwrap()), SCREEN_WIDTH, SCREEN_HEIGHT);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().unwrap().write_image_data(data).unwrap();
+}
+//the screen as rgb bytes once the test hits its breakpoint
+fn run(renderer: Renderer) -> Vec<u8> {
+    let rom = std::fs::read(data_path(ROM)).unwrap();
+    let mut gb = GameBoy::with_config(rom, Config { renderer, ..Config::default() }).unwrap();
+    let mut clocks = 0;
+    while !gb.take_breakpoint() {
+        assert!(clocks < FRAME_LIMIT * FRAME_CLOCKS, "no breakpoint after {} frames", FRAME_LIMIT);
+        clocks += gb.step_instruction() as u32;
+    }
+    gb.framebuffer()
+        .iter()
+        .flat_map(|pixel| [(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8])
+        .collect()
+}
+fn check(renderer: Renderer) {
+    let expected = read_png(&data_path(REFERENCE));
+    let actual = run(renderer);
+    let mismatches = expected.chunks(3).zip(actual.chunks(3)).filter(|(e, a)| e != a).count();
+    if mismatches == 0 {
+        return;
+    }
+    let diff: Vec<u8> = expected
+        .chunks(3)
+        .zip(actual.chunks(3))
+        .flat_map(|(e, a)| if e == a { e.iter().map(|c| 0xc0 + c / 4).collect() } else { DIFF_COLOR.to_vec() })
+        .collect();
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let name = format!("dmg-acid2-{:?}", renderer).to_lowercase();
+    let actual_path = out_dir.join(format!("{}.png", name));
+    let diff_path = out_dir.join(format!("{}-diff.png", name));
+    write_png(&actual_path, &actual);
+    write_png(&diff_path, &diff);
+    panic!(
+        "{} pixels differ from the reference, see {} and {}",
+        mismatches,
+        actual_path.display(),
+        diff_path.display()
+    );
+}
+#[test]
+fn scanline() {
+    check(Renderer::Scanline);
+}
+#[test]
+fn fifo() {
+    check(Renderer::Fifo);
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use rustboy_core::cartridge::RtcClock;
+use rustboy_core::{Config, GameBoy};
+
+//blargg's roms print their results over serial and end with "Passed" or
+//"Failed". they aren't checked in, point RUSTBOY_BLARGG_DIR at a copy of
+//the suite and run these with --ignored
+const DEFAULT_DIR: &str = "test_data/blargg";
+//cpu_instrs takes almost a minute of emulated time
+const FRAME_LIMIT: u32 = 60 * 120;
+
+//serial output of the rom
+fn run(rom: &str) -> String {
+    let rom = common::load_rom("RUSTBOY_BLARGG_DIR", DEFAULT_DIR, rom);
+    let config = Config { rtc_clock: RtcClock::Cycles, ..Config::default() };
+    let mut gb = GameBoy::with_config(rom, config).unwrap();
+    let mut output = String::new();
+    for _ in 0..FRAME_LIMIT {
+        gb.run_frame();
+        output.extend(gb.serial_output().into_iter().map(char::from));
+        if output.contains("Passed") || output.contains("Failed") {
+            break;
+        }
+    }
+    output
+}
+fn check(rom: &str) {
+    let output = run(rom);
+    assert!(output.contains("Passed"), "{}:\n{}", rom, output);
+}
+#[test]
+#[ignore = "needs blargg's test roms, see RUSTBOY_BLARGG_DIR"]
+fn cpu_instrs() {
+    check("cpu_instrs/cpu_instrs.gb");
+}
+#[test]
+#[ignore = "needs blargg's test roms, see RUSTBOY_BLARGG_DIR"]
+fn instr_timing() {
+    check("instr_timing/instr_timing.gb");
+}
@@ -0,0 +1,19 @@
+use std::path::PathBuf;
+
+//test roms aren't checked in, so the suites using them are #[ignore]d and
+//run with cargo test -- --ignored. each suite has an env var pointing at a
+//copy, otherwise it's looked for under test_data
+pub fn rom_dir(var: &str, default: &str) -> PathBuf {
+    match std::env::var_os(var) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(default),
+    }
+}
+//a missing rom fails the test, it never passes without running
+pub fn load_rom(var: &str, default: &str, rom: &str) -> Vec<u8> {
+    let path = rom_dir(var, default).join(rom);
+    match std::fs::read(&path) {
+        Ok(rom) => rom,
+        Err(e) => panic!("can't read {}: {}, set {} to the suite's directory", path.display(), e, var),
+    }
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use rustboy_core::cartridge::RtcClock;
+use rustboy_core::model::Model;
+use rustboy_core::{Config, CpuState, GameBoy, FRAME_CLOCKS};
+
+//mooneye's roms finish with LD B,B. a pass leaves the fibonacci numbers
+//3 5 8 13 21 34 in B C D E H L, a fail leaves 0x42 in all of them. point
+//RUSTBOY_MOONEYE_DIR at the suite's build directory and run these with
+//--ignored. the timing roms aren't listed, they need memory accesses timed
+//within an instruction and the cpu only syncs between instructions
+const DEFAULT_DIR: &str = "test_data/mooneye";
+//the slowest acceptance tests finish in a few seconds
+const FRAME_LIMIT: u32 = 60 * 10;
+const FIBONACCI: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+fn registers(state: &CpuState) -> [u8; 6] {
+    [state.b, state.c, state.d, state.e, state.h, state.l]
+}
+//registers at the breakpoint, Err if it was never reached
+fn run(rom: Vec<u8>, model: Option<Model>) -> Result<CpuState, String> {
+    let config = Config { rtc_clock: RtcClock::Cycles, model, ..Config::default() };
+    let mut gb = GameBoy::with_config(rom, config).map_err(|e| e.to_string())?;
+    let mut clocks = 0;
+    while clocks < FRAME_LIMIT * FRAME_CLOCKS {
+        clocks += gb.step_instruction() as u32;
+        if gb.take_breakpoint() {
+            return Ok(gb.cpu_state());
+        }
+    }
+    Err(format!("no breakpoint after {} frames", FRAME_LIMIT))
+}
+fn check(rom: &str, model: Option<Model>) {
+    let data = common::load_rom("RUSTBOY_MOONEYE_DIR", DEFAULT_DIR, rom);
+    match run(data, model) {
+        Ok(state) if registers(&state) == FIBONACCI => {}
+        Ok(state) => panic!("{} failed: {:02X?}", rom, state),
+        Err(e) => panic!("{}: {}", rom, e),
+    }
+}
+//roms for one model name it after the path, the rest run on whatever
+//the header picks
+macro_rules! mooneye {
+    ($($name:ident: $rom:expr $(=> $model:ident)?,)*) => {
+        $(
+            #[test]
+            #[ignore = "needs the mooneye test suite, see RUSTBOY_MOONEYE_DIR"]
+            fn $name() {
+                check($rom, mooneye!(@model $($model)?));
+            }
+        )*
+    };
+    (@model) => { None };
+    (@model $model:ident) => { Some(Model::$model) };
+}
+mooneye! {
+    boot_regs_dmg0: "acceptance/boot_regs-dmg0.gb" => Dmg0,
+    boot_regs_dmg: "acceptance/boot_regs-dmgABC.gb" => Dmg,
+    boot_regs_mgb: "acceptance/boot_regs-mgb.gb" => Mgb,
+    boot_regs_sgb: "acceptance/boot_regs-sgb.gb" => Sgb,
+    ei_sequence: "acceptance/ei_sequence.gb",
+    halt_ime0_ei: "acceptance/halt_ime0_ei.gb",
+    if_ie_registers: "acceptance/if_ie_registers.gb",
+    rapid_di_ei: "acceptance/rapid_di_ei.gb",
+    bits_mem_oam: "acceptance/bits/mem_oam.gb",
+    bits_reg_f: "acceptance/bits/reg_f.gb",
+    instr_daa: "acceptance/instr/daa.gb",
+    oam_dma_basic: "acceptance/oam_dma/basic.gb",
+    oam_dma_reg_read: "acceptance/oam_dma/reg_read.gb",
+    timer_div_write: "acceptance/timer/div_write.gb",
+}
@@ -0,0 +1,214 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use rustboy_core::cartridge::RtcClock;
+use rustboy_core::joypad::Buttons;
+use rustboy_core::model::Model;
+use rustboy_core::save::{self, Autosave};
+use rustboy_core::wav::WavWriter;
+use rustboy_core::{Config, GameBoy, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const USAGE: &str = "usage: rustboy-headless <rom> [options]
+  --frames N              run at most N frames (default 60)
+  --input FRAME:BUTTONS   hold BUTTONS from FRAME on, e.g. 30:a+start or 32:none
+  --script FILE           inputs from a file, one FRAME:BUTTONS per line
+  --screenshot FRAME:PNG  save the screen after FRAME frames
+  --until ADDR=VAL        stop once memory at ADDR (hex) reads VAL (hex)
+  --audio-out FILE.wav    record audio
+  --boot-rom FILE         run a dmg or cgb boot rom before the cartridge
+  --model MODEL           dmg0, dmg, mgb, sgb, cgb or agb (default from the header)
+  --no-save               don't load or write the .sav next to the rom
+
+exit status: 0 finished or the --until condition was met, 1 the condition
+wasn't met in time, 2 bad arguments, 3 the rom or an output file failed";
+
+const EXIT_TIMEOUT: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_ERROR: u8 = 3;
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Args {
+    rom: String,
+    frames: u32,
+    //sorted by frame
+    inputs: Vec<(u32, Buttons)>,
+    screenshots: Vec<(u32, String)>,
+    until: Option<(u16, u8)>,
+    audio_out: Option<String>,
+    boot_rom: Option<String>,
+    model: Option<Model>,
+    no_save: bool,
+}
+fn parse_buttons(names: &str) -> Result<Buttons, String> {
+    let mut buttons = Buttons::empty();
+    if names == "none" {
+        return Ok(buttons);
+    }
+    for name in names.split('+') {
+        buttons |= Buttons::from_name(&name.to_uppercase()).ok_or(format!("unknown button {}", name))?;
+    }
+    Ok(buttons)
+}
+fn parse_frame(val: &str) -> Result<u32, String> {
+    val.parse().map_err(|_| format!("bad frame number {}", val))
+}
+fn parse_input(val: &str) -> Result<(u32, Buttons), String> {
+    let (frame, buttons) = val.split_once(':').ok_or(format!("expected FRAME:BUTTONS, got {}", val))?;
+    Ok((parse_frame(frame)?, parse_buttons(buttons)?))
+}
+fn parse_hex(val: &str) -> Result<u16, String> {
+    let digits = val.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("bad hex number {}", val))
+}
+fn parse_args() -> Result<Args, String> {
+    let mut args = std::env::args().skip(1);
+    let mut parsed = Args {
+        rom: String::new(),
+        frames: 60,
+        inputs: Vec::new(),
+        screenshots: Vec::new(),
+        until: None,
+        audio_out: None,
+        boot_rom: None,
+        model: None,
+        no_save: false,
+    };
+    let mut rom = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--frames" => parsed.frames = parse_frame(&value()?)?,
+            "--input" => parsed.inputs.push(parse_input(&value()?)?),
+            "--script" => {
+                let path = value()?;
+                let script = std::fs::read_to_string(&path).map_err(|e| format!("can't read {}: {}", path, e))?;
+                for line in script.lines().map(str::trim) {
+                    if !line.is_empty() && !line.starts_with('#') {
+                        parsed.inputs.push(parse_input(line)?);
+                    }
+                }
+            }
+            "--screenshot" => {
+                let val = value()?;
+                let (frame, path) = val.split_once(':').ok_or(format!("expected FRAME:PNG, got {}", val))?;
+                parsed.screenshots.push((parse_frame(frame)?, path.to_string()));
+            }
+            "--until" => {
+                let val = value()?;
+                let (addr, expected) = val.split_once('=').ok_or(format!("expected ADDR=VAL, got {}", val))?;
+                let expected = u8::try_from(parse_hex(expected)?).map_err(|_| format!("{} is more than a byte", expected))?;
+                parsed.until = Some((parse_hex(addr)?, expected));
+            }
+            "--audio-out" => parsed.audio_out = Some(value()?),
+            "--no-save" => parsed.no_save = true,
+            "--boot-rom" => parsed.boot_rom = Some(value()?),
+            "--model" => {
+                let val = value()?;
+                parsed.model = Some(Model::from_name(&val.to_uppercase()).ok_or(format!("unknown model {}", val))?);
+            }
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    parsed.rom = rom.ok_or("no rom given")?;
+    parsed.inputs.sort_by_key(|(frame, _)| *frame);
+    Ok(parsed)
+}
+fn write_png(path: &str, framebuffer: &[u32]) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("can't create {}: {}", path, e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), SCREEN_WIDTH, SCREEN_HEIGHT);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    //0xAARRGGBB down to rgb bytes
+    let data: Vec<u8> = framebuffer
+        .iter()
+        .flat_map(|pixel| [(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8])
+        .collect();
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .map_err(|e| format!("can't write {}: {}", path, e))
+}
+//true if the --until condition was met
+fn run(args: &Args) -> Result<bool, String> {
+    let rom = std::fs::read(&args.rom).map_err(|e| format!("can't read {}: {}", args.rom, e))?;
+    let boot_rom = match &args.boot_rom {
+        Some(path) => Some(std::fs::read(path).map_err(|e| format!("can't read {}: {}", path, e))?),
+        None => None,
+    };
+    //the mbc3 clock follows emulated time so runs don't depend on when they happen
+    let config = Config { rtc_clock: RtcClock::Cycles, boot_rom, model: args.model, ..Config::default() };
+    let mut gb = GameBoy::with_config(rom, config).map_err(|e| e.to_string())?;
+    if let Err(e) = gb.cartridge().check_header_checksum() {
+        eprintln!("warning: {}", e);
+    }
+    let save_path = (!args.no_save).then(|| save::save_path(Path::new(&args.rom)));
+    if let Some(path) = &save_path {
+        save::load(&mut gb.cartridge_mut(), path).map_err(|e| format!("can't load {}: {}", path.display(), e))?;
+    }
+    let mut autosave = save_path.map(|path| Autosave::new(path, AUTOSAVE_INTERVAL));
+    let met = run_frames(&mut gb, args, autosave.as_mut());
+    //write the battery ram out even if the run stopped on an error
+    if let Some(autosave) = &mut autosave {
+        autosave.flush(&gb.cartridge()).map_err(|e| format!("can't save: {}", e))?;
+    }
+    met
+}
+fn run_frames(gb: &mut GameBoy, args: &Args, mut autosave: Option<&mut Autosave>) -> Result<bool, String> {
+    let mut wav = match &args.audio_out {
+        Some(path) => Some(WavWriter::create(path, gb.sample_rate()).map_err(|e| format!("can't create {}: {}", path, e))?),
+        None => None,
+    };
+    let mut inputs = args.inputs.iter().peekable();
+    let mut met = false;
+    for frame in 0..args.frames {
+        while let Some((_, buttons)) = inputs.next_if(|(at, _)| *at <= frame) {
+            gb.set_buttons(*buttons);
+        }
+        gb.run_frame();
+        let samples = gb.audio_samples();
+        if let Some(wav) = &mut wav {
+            wav.write_samples(&samples).map_err(|e| e.to_string())?;
+        }
+        if let Some(autosave) = &mut autosave {
+            autosave.poll(&gb.cartridge()).map_err(|e| format!("can't save: {}", e))?;
+        }
+        //frames are counted from 1 for screenshots, "after 60 frames"
+        for (_, path) in args.screenshots.iter().filter(|(at, _)| *at == frame + 1) {
+            write_png(path, gb.framebuffer())?;
+        }
+        if let Some((addr, expected)) = args.until {
+            if gb.peek(addr) == expected {
+                met = true;
+                break;
+            }
+        }
+    }
+    if let Some(wav) = wav {
+        wav.finish().map_err(|e| e.to_string())?;
+    }
+    Ok(met || args.until.is_none())
+}
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => {
+            eprintln!("condition not met after {} frames", args.frames);
+            ExitCode::from(EXIT_TIMEOUT)
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}